@@ -0,0 +1,18 @@
+use piece::{PIECE_TYPES, PieceType};
+use rand::Rng;
+
+/// Deals out every `PieceType` once, in a shuffled order, before refilling.
+#[derive(Clone, Default)]
+pub struct Bag {
+    pieces: Vec<PieceType>
+}
+
+impl Bag {
+    pub fn next<R: Rng>(&mut self, rng: &mut R) -> PieceType {
+        if self.pieces.is_empty() {
+            self.pieces.extend_from_slice(&PIECE_TYPES);
+            rng.shuffle(&mut self.pieces);
+        }
+        self.pieces.pop().expect("Bag was refilled")
+    }
+}
@@ -0,0 +1,255 @@
+//! Which keys do what.
+//!
+//! The defaults can be changed in `config.toml` in the config directory,
+//! where each action lists every key that does it:
+//!
+//! ```toml
+//! [commands]
+//! quit = ["Escape"]
+//! confirm = ["Return", "KP_Enter"]
+//! menu_up = ["Up", "k"]
+//!
+//! [solo]
+//! rotate_left = ["z", "code:44"]
+//! hard_drop = ["space", "Up"]
+//! ```
+//!
+//! Keys are either keysym names, which follow the keyboard layout, or
+//! `code:` and a keycode, which stays in the same place on any layout. The
+//! players of a versus game have their own `[versus_left]` and
+//! `[versus_right]` sections.
+//!
+//! Keycodes are the kernel's evdev codes, as listed in
+//! `linux/input-event-codes.h` and shown by `evtest`, so `code:44` is the key
+//! labeled Z on a US keyboard. Tools that show X11 or XKB keycodes, like
+//! `xev` and `xkbcli`, number every key 8 higher.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use tetris_wlroots::Action;
+use toml::Value;
+use wlroots::xkbcommon::xkb::{self, Keysym, KEY_Down, KEY_Escape, KEY_Left, KEY_Return,
+                              KEY_Right, KEY_Tab, KEY_Up, KEY_a, KEY_c, KEY_comma, KEY_d, KEY_e,
+                              KEY_f, KEY_g, KEY_p, KEY_period, KEY_q, KEY_r, KEY_s, KEY_slash,
+                              KEY_space, KEY_w, KEY_x, KEY_z};
+
+/// A key, either by what it types or by where it is on the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Sym(Keysym),
+    Code(u32)
+}
+
+impl Key {
+    /// Parse a key from a keysym name, like `Left` or `z`, or from
+    /// `code:<keycode>`.
+    fn from_name(name: &str) -> Option<Self> {
+        if name.starts_with("code:") {
+            return name["code:".len()..].parse().ok().map(Key::Code)
+        }
+        match xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS) {
+            xkb::KEY_NoSymbol => None,
+            sym => Some(Key::Sym(sym))
+        }
+    }
+
+    /// Whether this is the key that was pressed or released, given what it
+    /// typed and its keycode.
+    ///
+    /// The keycode is the evdev code wlroots reports in the key event, before
+    /// the 8 is added to look up its keysyms.
+    fn matches(self, keysyms: &[Keysym], keycode: u32) -> bool {
+        match self {
+            Key::Sym(sym) => keysyms.contains(&sym),
+            Key::Code(code) => code == keycode
+        }
+    }
+}
+
+/// Something a key does outside of any one player's game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Quit,
+    Restart,
+    /// Play again once a game is over.
+    Confirm,
+    ToggleGhost,
+    /// Switch which game the keyboard plays.
+    Focus,
+    /// Pause everyone playing the game.
+    Pause,
+    /// Move up a menu.
+    MenuUp,
+    /// Move down a menu.
+    MenuDown
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "quit" => Some(Command::Quit),
+            "restart" => Some(Command::Restart),
+            "confirm" => Some(Command::Confirm),
+            "ghost" => Some(Command::ToggleGhost),
+            "focus" => Some(Command::Focus),
+            "pause" => Some(Command::Pause),
+            "menu_up" => Some(Command::MenuUp),
+            "menu_down" => Some(Command::MenuDown),
+            _ => None
+        }
+    }
+}
+
+/// Where a player sits, which decides which keys they play with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seat {
+    Solo,
+    VersusLeft,
+    VersusRight
+}
+
+/// Every key that does something.
+pub struct Bindings {
+    commands: Vec<(Key, Command)>,
+    solo: Vec<(Key, Action)>,
+    versus_left: Vec<(Key, Action)>,
+    versus_right: Vec<(Key, Action)>
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use self::Action::*;
+        let keys = |keys: &[(Keysym, Action)]| {
+            keys.iter().map(|&(sym, action)| (Key::Sym(sym), action)).collect()
+        };
+        Bindings { commands: vec![(Key::Sym(KEY_Escape), Command::Quit),
+                                  (Key::Sym(KEY_r), Command::Restart),
+                                  (Key::Sym(KEY_Return), Command::Confirm),
+                                  (Key::Sym(KEY_g), Command::ToggleGhost),
+                                  (Key::Sym(KEY_Tab), Command::Focus),
+                                  (Key::Sym(KEY_p), Command::Pause),
+                                  (Key::Sym(KEY_Up), Command::MenuUp),
+                                  (Key::Sym(KEY_w), Command::MenuUp),
+                                  (Key::Sym(KEY_Down), Command::MenuDown),
+                                  (Key::Sym(KEY_s), Command::MenuDown)],
+                   solo: keys(&[(KEY_Left, Left),
+                                (KEY_Right, Right),
+                                (KEY_Down, SoftDrop),
+                                (KEY_space, HardDrop),
+                                (KEY_z, RotateLeft),
+                                (KEY_x, RotateRight),
+                                (KEY_c, Hold)]),
+                   versus_left: keys(&[(KEY_a, Left),
+                                       (KEY_d, Right),
+                                       (KEY_s, SoftDrop),
+                                       (KEY_w, HardDrop),
+                                       (KEY_q, RotateLeft),
+                                       (KEY_e, RotateRight),
+                                       (KEY_f, Hold)]),
+                   versus_right: keys(&[(KEY_Left, Left),
+                                        (KEY_Right, Right),
+                                        (KEY_Down, SoftDrop),
+                                        (KEY_Up, HardDrop),
+                                        (KEY_comma, RotateLeft),
+                                        (KEY_period, RotateRight),
+                                        (KEY_slash, Hold)]) }
+    }
+}
+
+impl Bindings {
+    /// Load the bindings from the config file, keeping the default keys for
+    /// anything it doesn't mention.
+    pub fn load() -> Self {
+        let mut bindings = Bindings::default();
+        let path = match config_dir() {
+            Some(dir) => dir.join("config.toml"),
+            None => return bindings
+        };
+        let mut contents = String::new();
+        if File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+            return bindings
+        }
+        let config = match contents.parse::<Value>() {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Could not parse {}: {}", path.display(), err);
+                return bindings
+            }
+        };
+        bind(&mut bindings.commands, config.get("commands"), Command::from_name);
+        bind(&mut bindings.solo, config.get("solo"), Action::from_name);
+        bind(&mut bindings.versus_left, config.get("versus_left"), Action::from_name);
+        bind(&mut bindings.versus_right, config.get("versus_right"), Action::from_name);
+        bindings
+    }
+
+    /// The commands done by a key.
+    pub fn commands(&self, keysyms: &[Keysym], keycode: u32) -> Vec<Command> {
+        find(&self.commands, keysyms, keycode)
+    }
+
+    /// The actions a key does for the player in a seat.
+    pub fn actions(&self, seat: Seat, keysyms: &[Keysym], keycode: u32) -> Vec<Action> {
+        let keys = match seat {
+            Seat::Solo => &self.solo,
+            Seat::VersusLeft => &self.versus_left,
+            Seat::VersusRight => &self.versus_right
+        };
+        find(keys, keysyms, keycode)
+    }
+}
+
+/// Where settings are kept.
+///
+/// This is `$XDG_CONFIG_HOME/tetris-wlroots`, falling back to
+/// `~/.config/tetris-wlroots`.
+fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config")
+    };
+    Some(base.join("tetris-wlroots"))
+}
+
+/// Everything bound to a key.
+fn find<T: Copy>(keys: &[(Key, T)], keysyms: &[Keysym], keycode: u32) -> Vec<T> {
+    keys.iter()
+        .filter(|&&(key, _)| key.matches(keysyms, keycode))
+        .map(|&(_, bound)| bound)
+        .collect()
+}
+
+/// Replace the keys for everything a section of the config lists.
+fn bind<T: Copy + PartialEq>(keys: &mut Vec<(Key, T)>,
+                             section: Option<&Value>,
+                             from_name: fn(&str) -> Option<T>) {
+    let section = match section.and_then(Value::as_table) {
+        Some(section) => section,
+        None => return
+    };
+    for (name, names) in section {
+        let bound = match from_name(name) {
+            Some(bound) => bound,
+            None => {
+                eprintln!("Ignoring keys for unknown action {}", name);
+                continue
+            }
+        };
+        let names = match names.as_array() {
+            Some(names) => names,
+            None => {
+                eprintln!("Ignoring keys for {}, which should be a list", name);
+                continue
+            }
+        };
+        keys.retain(|&(_, other)| other != bound);
+        for key_name in names {
+            match key_name.as_str().and_then(Key::from_name) {
+                Some(key) => keys.push((key, bound)),
+                None => eprintln!("Ignoring unknown key {} for {}", key_name, name)
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Blue,
+    Purple,
+    Orange,
+    Yellow,
+    Red,
+    Grey,
+    DarkGrey,
+    Garbage,
+    Green,
+    Pink,
+    TransparentRed,
+    TransparentBlue
+}
+
+impl Color {
+    pub fn border() -> Self {
+        Color::Grey
+    }
+
+    pub fn background() -> Self {
+        Color::DarkGrey
+    }
+
+    pub fn dead() -> Self {
+        Color::TransparentRed
+    }
+
+    pub fn garbage() -> Self {
+        Color::Garbage
+    }
+
+    pub fn paused() -> Self {
+        Color::TransparentBlue
+    }
+
+    /// A faded version of the color, for the ghost of the falling piece.
+    pub fn ghost(self) -> [f32; 4] {
+        let mut color: [f32; 4] = self.into();
+        for channel in &mut color {
+            *channel *= 0.3;
+        }
+        color
+    }
+}
+
+impl From<Color> for [f32; 4] {
+    fn from(color: Color) -> Self {
+        use Color::*;
+        match color {
+            Blue => [0.0, 0.0, 1.0, 1.0],
+            Orange => [1.0, 0.41, 0.0, 1.0],
+            Yellow => [1.0, 1.0, 0.0, 1.0],
+            Red => [1.0, 0.0, 0.0, 1.0],
+            Green => [0.0, 1.0, 0.0, 1.0],
+            Pink => [1.0, 0.4117, 0.713, 1.0],
+            Purple => [0.9333, 0.50980, 0.9333, 1.0],
+            Grey => [0.50, 0.50, 0.50, 1.0],
+            DarkGrey => [0.25, 0.25, 0.25, 1.0],
+            Garbage => [0.62, 0.55, 0.45, 1.0],
+            TransparentRed => [0.5, 0.0, 0.0, 0.1],
+            TransparentBlue => [0.0, 0.0, 0.5, 0.1],
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use piece::{Dir, Piece, Rotation};
+use std::collections::VecDeque;
+use {BOARD_HEIGHT, BOARD_WIDTH};
+
+/// The fewest inputs it takes to get a freshly spawned piece into the same
+/// columns and orientation as `target` on an empty board.
+///
+/// Moving or rotating once is one input, and so is holding a direction to
+/// shift all the way to the wall. Rotations kick off the walls the same way
+/// they do in the game. Orientations that fill the same cells, like the two
+/// flat S pieces, count as the same placement.
+pub fn min_inputs(target: Piece) -> usize {
+    let goal = footprint(target);
+    let start = Piece::new(target.ty);
+    let mut seen: Vec<(i32, Rotation)> = vec![(start.x_offset, start.rotation)];
+    let mut queue = VecDeque::new();
+    queue.push_back((start, 0));
+    while let Some((piece, inputs)) = queue.pop_front() {
+        if footprint(piece) == goal {
+            return inputs
+        }
+        let moves = [Some(piece.move_left()),
+                     Some(piece.move_right()),
+                     Some(shift_to_wall(piece, Dir::Left)),
+                     Some(shift_to_wall(piece, Dir::Right)),
+                     piece.rotate_kicked(Dir::Left, fits).map(|(next, _)| next),
+                     piece.rotate_kicked(Dir::Right, fits).map(|(next, _)| next)];
+        for &next in moves.iter().flat_map(Option::iter) {
+            let key = (next.x_offset, next.rotation);
+            if fits(next) && !seen.contains(&key) {
+                seen.push(key);
+                queue.push_back((next, inputs + 1));
+            }
+        }
+    }
+    // Every placement on the board can be reached, but be lenient if not
+    usize::MAX
+}
+
+/// The cells a piece covers, moved up against the top of the board.
+fn footprint(piece: Piece) -> Vec<(i32, i32)> {
+    let coords = piece.coords();
+    let top = coords.iter().map(|point| point.y).min().unwrap_or(0);
+    let mut cells: Vec<_> = coords.iter().map(|point| (point.x, point.y - top)).collect();
+    cells.sort();
+    cells
+}
+
+/// Whether a piece fits on an empty board, between the walls and above the
+/// floor.
+fn fits(piece: Piece) -> bool {
+    piece.coords()
+         .iter()
+         .all(|point| point.x >= 0 && point.x < BOARD_WIDTH as i32 && point.y < BOARD_HEIGHT as i32)
+}
+
+fn shift_to_wall(mut piece: Piece, dir: Dir) -> Piece {
+    loop {
+        let next = match dir {
+            Dir::Left => piece.move_left(),
+            Dir::Right => piece.move_right()
+        };
+        if !fits(next) {
+            return piece
+        }
+        piece = next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::{Action, Handling, Input, Mode, Tetris};
+    use piece::PieceType;
+    use std::time::Duration;
+
+    fn placed(ty: PieceType, rotations: &[Dir], x_offset: i32) -> Piece {
+        let mut piece = Piece::new(ty);
+        for &dir in rotations {
+            piece = piece.rotate(dir);
+        }
+        piece.x_offset = x_offset;
+        piece
+    }
+
+    #[test]
+    fn known_placements() {
+        use piece::PieceType::*;
+        assert_eq!(min_inputs(Piece::new(T)), 0);
+        assert_eq!(min_inputs(placed(Block, &[], 0)), 1);
+        assert_eq!(min_inputs(placed(Block, &[], 3)), 1);
+        assert_eq!(min_inputs(placed(T, &[], 0)), 1);
+        assert_eq!(min_inputs(placed(T, &[], 1)), 2);
+        assert_eq!(min_inputs(placed(T, &[Dir::Right, Dir::Right], 3)), 2);
+        // A vertical I in the columns it rotates into at spawn
+        assert_eq!(min_inputs(placed(I, &[Dir::Right], 3)), 1);
+        assert_eq!(min_inputs(placed(I, &[Dir::Left], 3)), 1);
+        assert_eq!(min_inputs(placed(I, &[Dir::Right], -2)), 2);
+        assert_eq!(min_inputs(placed(J, &[Dir::Right], 7)), 2);
+        // Both flat S pieces fill the same cells
+        assert_eq!(min_inputs(placed(S, &[Dir::Right, Dir::Right], 3)), 0);
+    }
+
+    #[test]
+    fn faults_are_counted_on_lock() {
+        let play = |actions: &[Action]| {
+            let mut tetris = Tetris::new(Mode::Sprint, Handling::default(), 0);
+            tetris.current = Piece::new(PieceType::I);
+            for &action in actions {
+                tetris.step(Duration::from_secs(0), &[Input::Press(action)]);
+            }
+            tetris.step(Duration::from_secs(0), &[Input::Press(Action::HardDrop)]);
+            tetris.finesse_faults
+        };
+        assert_eq!(play(&[Action::RotateRight]), 0);
+        assert_eq!(play(&[Action::RotateLeft, Action::RotateLeft, Action::RotateLeft]), 1);
+        assert_eq!(play(&[Action::Left, Action::Left, Action::Left]), 1);
+    }
+}
@@ -0,0 +1,1304 @@
+use bag::Bag;
+use color::Color;
+use finesse;
+use piece::{Dir, Piece, PieceType, Point, Rotation};
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+use std::time::Duration;
+use {BOARD_HEIGHT, BOARD_WIDTH};
+
+/// How many lines have to be cleared to go up a level.
+const LINES_PER_LEVEL: usize = 10;
+/// The level where gravity becomes 20G, and stops getting any faster.
+const MAX_LEVEL: usize = 20;
+/// 20 rows every 60th of a second, in rows per millisecond.
+const TWENTY_G: f64 = 20.0 * 60.0 / 1000.0;
+/// How many times faster than gravity pieces fall while soft dropping.
+const SOFT_DROP_FACTOR: f64 = 20.0;
+/// How many lines have to be cleared to finish a sprint.
+pub const SPRINT_LINES: usize = 40;
+/// How many seconds a score attack lasts, unless it's chosen otherwise.
+pub const ULTRA_SECONDS: u64 = 120;
+/// How many rows of garbage have to be dug through.
+pub const DIG_ROWS: usize = 10;
+/// How many extra rows of garbage are sent for each clear in a combo, starting
+/// from the first clear.
+const COMBO_ATTACK: [usize; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+/// How many milliseconds to show a callout for a special clear.
+const CALLOUT_MS: f64 = 1500.0;
+/// How many milliseconds a piece can rest on the stack before it locks.
+const LOCK_DELAY_MS: f64 = 500.0;
+/// How many times moving or rotating can restart the lock delay before the
+/// piece reaches a new lowest row.
+const MAX_LOCK_RESETS: u32 = 15;
+
+/// Whether a T piece was spun into place when it locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full
+}
+
+/// How long, in milliseconds, a direction has to be held before it starts
+/// repeating (Delayed Auto Shift), and then how long between each repeat
+/// (Auto Repeat Rate). An ARR of 0 moves straight to the wall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handling {
+    pub das: u64,
+    pub arr: u64
+}
+
+impl Default for Handling {
+    fn default() -> Self {
+        Handling { das: 167, arr: 33 }
+    }
+}
+
+/// A direction that is being held down.
+#[derive(Clone, Copy)]
+struct Shift {
+    dir: Dir,
+    /// How many milliseconds it has been held for.
+    held: f64,
+    /// How many times it has auto repeated so far.
+    repeats: u64
+}
+
+impl Shift {
+    fn new(dir: Dir) -> Self {
+        Shift { dir, held: 0.0, repeats: 0 }
+    }
+}
+
+/// The goal of a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Keep going for as long as possible.
+    Marathon,
+    /// Clear 40 lines as fast as possible.
+    Sprint,
+    /// Score as much as possible before the given number of seconds is up.
+    Ultra(u64),
+    /// Clear all the garbage at the bottom of the board as fast as possible.
+    Dig,
+    /// Outlast another player, sending garbage to them by clearing lines.
+    Versus
+}
+
+impl Mode {
+    /// The name used for the mode when saving it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Marathon => "marathon",
+            Mode::Sprint => "sprint",
+            Mode::Ultra(_) => "ultra",
+            Mode::Dig => "dig",
+            Mode::Versus => "versus"
+        }
+    }
+
+    /// The name of the mode's high score table, which keeps each length of
+    /// ultra apart so games are only ranked against ones just as long.
+    pub fn table_name(self) -> String {
+        match self {
+            Mode::Ultra(seconds) => format!("ultra-{}", seconds),
+            mode => mode.name().to_string()
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "marathon" => Some(Mode::Marathon),
+            "sprint" => Some(Mode::Sprint),
+            "ultra" => Some(Mode::Ultra(ULTRA_SECONDS)),
+            "dig" => Some(Mode::Dig),
+            "versus" => Some(Mode::Versus),
+            _ => None
+        }
+    }
+
+    /// Whether the mode is won by the time taken, rather than the score.
+    pub fn is_race(self) -> bool {
+        matches!(self, Mode::Sprint | Mode::Dig)
+    }
+
+    /// How long the game lasts, if it's played against the clock.
+    pub fn time_limit(self) -> Option<Duration> {
+        match self {
+            Mode::Ultra(seconds) => Some(Duration::from_secs(seconds)),
+            _ => None
+        }
+    }
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Marathon
+    }
+}
+
+/// Something the player can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Left,
+    Right,
+    SoftDrop,
+    HardDrop,
+    RotateLeft,
+    RotateRight,
+    Hold,
+    Pause
+}
+
+impl Action {
+    /// The name used for the action when saving it.
+    pub fn name(self) -> &'static str {
+        use self::Action::*;
+        match self {
+            Left => "left",
+            Right => "right",
+            SoftDrop => "soft_drop",
+            HardDrop => "hard_drop",
+            RotateLeft => "rotate_left",
+            RotateRight => "rotate_right",
+            Hold => "hold",
+            Pause => "pause"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        use self::Action::*;
+        Some(match name {
+            "left" => Left,
+            "right" => Right,
+            "soft_drop" => SoftDrop,
+            "hard_drop" => HardDrop,
+            "rotate_left" => RotateLeft,
+            "rotate_right" => RotateRight,
+            "hold" => Hold,
+            "pause" => Pause,
+            _ => return None
+        })
+    }
+}
+
+/// An action's key being pressed or released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Press(Action),
+    Release(Action)
+}
+
+/// The state of a single game.
+///
+/// Nothing happens on its own: the game only moves forward when it's given
+/// time and inputs through `step`.
+#[derive(Clone)]
+pub struct Tetris {
+    pub mode: Mode,
+    pub board: [[Option<Color>; BOARD_WIDTH]; BOARD_HEIGHT],
+    pub current: Piece,
+    pub next: Piece,
+    pub hold: Option<Piece>,
+    pub can_hold: bool,
+    bag: Bag,
+    /// The seed that `rng` started from, which decides every piece dealt.
+    pub seed: u64,
+    rng: XorShiftRng,
+    /// Decides where the holes in garbage go, kept apart from `rng` so that
+    /// garbage doesn't change which pieces are dealt.
+    garbage_rng: XorShiftRng,
+    /// How many times the game has been stepped.
+    pub ticks: u64,
+    /// How far the current piece has fallen towards the next row.
+    fall_progress: f64,
+    pub handling: Handling,
+    left: bool,
+    right: bool,
+    shift: Option<Shift>,
+    soft_drop: bool,
+    /// How many milliseconds the current piece has been resting on the
+    /// stack, if it is.
+    lock_delay: Option<f64>,
+    lock_resets: u32,
+    /// The lowest row the current piece has reached, which refills the
+    /// lock resets when it's passed.
+    lowest_row: i32,
+    /// Which wall kick was used, if the last thing the current piece did was
+    /// rotate.
+    last_rotation: Option<usize>,
+    pub lost: bool,
+    /// Whether the goal of the mode has been reached.
+    pub finished: bool,
+    pub pause: bool,
+    /// How much time has been played, not counting pauses.
+    pub elapsed: Duration,
+    /// How many pieces have been locked.
+    pub pieces: usize,
+    /// How many inputs have been used on the current piece, not counting
+    /// auto repeats.
+    piece_inputs: usize,
+    /// Whether the current piece has been soft dropped, which makes it too
+    /// hard to tell what the most efficient inputs would have been.
+    soft_dropped: bool,
+    /// How many pieces took more inputs to place than they needed to.
+    pub finesse_faults: usize,
+    pub score: usize,
+    /// How many rows have been cleared in total.
+    pub lines: usize,
+    pub level: usize,
+    /// How many pieces in a row have cleared lines, counting from 0.
+    combo: Option<usize>,
+    /// Whether the last clear was a difficult one, which earns a bonus if the
+    /// next clear is difficult too.
+    back_to_back: bool,
+    /// Rows of garbage that have been received, which rise up from the
+    /// bottom the next time a piece locks without clearing anything.
+    pub pending_garbage: usize,
+    /// Rows of garbage waiting to be sent with `take_attack`.
+    attack: usize,
+    /// How many rows of garbage have been sent in total.
+    pub garbage_sent: usize,
+    /// The name of the last special clear, and how many more milliseconds
+    /// to show it for.
+    pub callout: Option<(String, f64)>
+}
+
+impl Default for Tetris {
+    fn default() -> Self {
+        Tetris::new(Mode::default(), Handling::default(), rand::random())
+    }
+}
+
+impl Tetris {
+    /// Start a new game, where the pieces are decided by the seed.
+    pub fn new(mode: Mode, handling: Handling, seed: u64) -> Self {
+        let mut rng = seeded_rng(seed);
+        let mut garbage_rng = seeded_rng(!seed);
+        let mut board = [[None; BOARD_WIDTH]; BOARD_HEIGHT];
+        if mode == Mode::Dig {
+            fill_garbage(&mut board, DIG_ROWS, &mut garbage_rng);
+        }
+        let mut bag = Bag::default();
+        let current = Piece::random(&mut bag, &mut rng);
+        let next = Piece::random(&mut bag, &mut rng).tease();
+        Tetris { mode,
+                 board,
+                 current,
+                 next,
+                 hold: None,
+                 can_hold: true,
+                 bag,
+                 seed,
+                 rng,
+                 garbage_rng,
+                 ticks: 0,
+                 fall_progress: 0.0,
+                 handling,
+                 left: false,
+                 right: false,
+                 shift: None,
+                 soft_drop: false,
+                 lock_delay: None,
+                 lock_resets: 0,
+                 lowest_row: current.y_offset,
+                 last_rotation: None,
+                 lost: false,
+                 finished: false,
+                 pause: false,
+                 elapsed: Duration::from_secs(0),
+                 pieces: 0,
+                 piece_inputs: 0,
+                 soft_dropped: false,
+                 finesse_faults: 0,
+                 score: 0,
+                 lines: 0,
+                 level: 1,
+                 combo: None,
+                 back_to_back: false,
+                 pending_garbage: 0,
+                 attack: 0,
+                 garbage_sent: 0,
+                 callout: None }
+    }
+
+    /// Start a new game with a new seed, keeping the same settings.
+    pub fn restart(&mut self, seed: u64) {
+        *self = Tetris::new(self.mode, self.handling, seed)
+    }
+
+    /// How many pieces have been locked per second of play.
+    pub fn pieces_per_second(&self) -> f64 {
+        let seconds = millis(self.elapsed) / 1000.0;
+        if seconds == 0.0 {
+            return 0.0
+        }
+        self.pieces as f64 / seconds
+    }
+
+    /// Take the rows of garbage sent by clearing lines since the last time
+    /// this was called, to give to an opponent.
+    pub fn take_attack(&mut self) -> usize {
+        std::mem::replace(&mut self.attack, 0)
+    }
+
+    /// Queue up rows of garbage sent by an opponent.
+    pub fn receive_garbage(&mut self, rows: usize) {
+        self.pending_garbage += rows;
+    }
+
+    /// How many rows still have garbage in them.
+    pub fn garbage_rows(&self) -> usize {
+        self.board.iter().filter(|row| row.contains(&Some(Color::Garbage))).count()
+    }
+
+    /// How long is left before time runs out, if the game is timed.
+    pub fn time_left(&self) -> Option<Duration> {
+        self.mode.time_limit().map(|limit| {
+            if limit > self.elapsed {
+                limit - self.elapsed
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// Determines if the game has ended, either by losing or by reaching the
+    /// goal.
+    pub fn over(&self) -> bool {
+        self.lost || self.finished
+    }
+
+    /// Advance the game by `dt`, after applying the inputs that happened
+    /// since the last step.
+    pub fn step(&mut self, dt: Duration, inputs: &[Input]) {
+        self.ticks += 1;
+        for &input in inputs {
+            self.apply(input)
+        }
+        if self.over() || self.pause {
+            return
+        }
+        self.elapsed += dt;
+        if let Some(limit) = self.mode.time_limit() {
+            if self.elapsed >= limit {
+                self.elapsed = limit;
+                self.finished = true;
+                return
+            }
+        }
+        let dt = millis(dt);
+        self.step_callout(dt);
+        self.auto_shift(dt);
+        // Simulate gravity, faster while soft dropping
+        self.step_gravity(dt);
+        // Once it lands, give the piece some time before locking it in place
+        self.step_lock(dt);
+    }
+
+    /// Apply a single input.
+    ///
+    /// Releases always go through so that held keys don't get stuck, but
+    /// presses are ignored while paused or over, except to unpause.
+    fn apply(&mut self, input: Input) {
+        use self::Action::*;
+        match input {
+            Input::Press(Pause) if !self.over() => self.pause = !self.pause,
+            Input::Press(_) if self.over() || self.pause => {},
+            Input::Press(Left) => {
+                self.piece_inputs += 1;
+                self.press_dir(Dir::Left)
+            },
+            Input::Press(Right) => {
+                self.piece_inputs += 1;
+                self.press_dir(Dir::Right)
+            },
+            Input::Press(SoftDrop) => {
+                self.soft_dropped = true;
+                self.soft_drop = true
+            },
+            Input::Press(HardDrop) => self.hard_drop(),
+            Input::Press(RotateLeft) => {
+                self.piece_inputs += 1;
+                self.rotate(Dir::Left)
+            },
+            Input::Press(RotateRight) => {
+                self.piece_inputs += 1;
+                self.rotate(Dir::Right)
+            },
+            Input::Press(Hold) => self.hold(),
+            Input::Press(Pause) => {},
+            Input::Release(Left) => self.release_dir(Dir::Left),
+            Input::Release(Right) => self.release_dir(Dir::Right),
+            Input::Release(SoftDrop) => self.soft_drop = false,
+            Input::Release(_) => {}
+        }
+    }
+
+    /// Attempts to move the current piece in the given direction.
+    ///
+    /// If it would be blocked, then it will not change and false is returned.
+    fn move_dir(&mut self, dir: Dir) -> bool {
+        let next_move = match dir {
+            Dir::Left => self.current.move_left(),
+            Dir::Right => self.current.move_right()
+        };
+        if self.collide(next_move.coords()) {
+            return false
+        }
+        self.current = next_move;
+        self.last_rotation = None;
+        self.moved();
+        true
+    }
+
+    /// A direction key was pressed: move once, and start auto shifting.
+    fn press_dir(&mut self, dir: Dir) {
+        match dir {
+            Dir::Left => self.left = true,
+            Dir::Right => self.right = true
+        }
+        self.move_dir(dir);
+        self.shift = Some(Shift::new(dir));
+    }
+
+    /// A direction key was released.
+    ///
+    /// If the other direction is still held it takes over the auto shift.
+    fn release_dir(&mut self, dir: Dir) {
+        match dir {
+            Dir::Left => self.left = false,
+            Dir::Right => self.right = false
+        }
+        let shifting = match self.shift {
+            Some(shift) => shift.dir,
+            None => return
+        };
+        if shifting != dir {
+            return
+        }
+        self.shift = match dir {
+            Dir::Left if self.right => Some(Shift::new(Dir::Right)),
+            Dir::Right if self.left => Some(Shift::new(Dir::Left)),
+            _ => None
+        };
+    }
+
+    /// Repeat the held direction, once it has been held for longer than the
+    /// DAS, every ARR milliseconds.
+    fn auto_shift(&mut self, dt: f64) {
+        let mut shift = match self.shift {
+            Some(shift) => shift,
+            None => return
+        };
+        shift.held += dt;
+        let das = self.handling.das as f64;
+        if shift.held >= das {
+            if self.handling.arr == 0 {
+                while self.move_dir(shift.dir) {}
+            } else {
+                let repeats = ((shift.held - das) / self.handling.arr as f64) as u64 + 1;
+                while shift.repeats < repeats {
+                    self.move_dir(shift.dir);
+                    shift.repeats += 1;
+                }
+            }
+        }
+        self.shift = Some(shift);
+    }
+
+    /// Attempt to rotate the current piece in the given direction.
+    ///
+    /// Each of the SRS wall kicks is tried in order, and the first one that
+    /// fits is used. If they are all blocked, then it will not change.
+    fn rotate(&mut self, dir: Dir) {
+        let rotated = self.current.rotate_kicked(dir, |piece| !self.collide(piece.coords()));
+        if let Some((next_move, kick)) = rotated {
+            self.current = next_move;
+            self.last_rotation = Some(kick);
+            self.moved();
+        }
+    }
+
+    /// Move the current piece down a row, if it can.
+    ///
+    /// Each row fallen while soft dropping is worth a point.
+    fn fall(&mut self) -> bool {
+        let next_move = self.current.move_down();
+        if self.collide(next_move.coords()) {
+            return false
+        }
+        self.current = next_move;
+        self.last_rotation = None;
+        self.moved();
+        if self.soft_drop {
+            self.score += 1;
+        }
+        true
+    }
+
+    /// Simulate gravity for the given number of milliseconds.
+    ///
+    /// Whole rows are fallen as soon as enough progress builds up, so at high
+    /// levels a piece can fall several rows at once.
+    fn step_gravity(&mut self, dt: f64) {
+        let mut rate = gravity(self.level);
+        if self.soft_drop {
+            rate *= SOFT_DROP_FACTOR;
+        }
+        self.fall_progress += rate * dt;
+        while self.fall_progress >= 1.0 {
+            self.fall_progress -= 1.0;
+            if !self.fall() {
+                self.fall_progress = 0.0;
+            }
+        }
+    }
+
+    /// Determines if the current piece is resting on the stack or the floor.
+    fn grounded(&self) -> bool {
+        self.collide(self.current.move_down().coords())
+    }
+
+    /// Lock the current piece once it has rested on the stack for long enough.
+    fn step_lock(&mut self, dt: f64) {
+        if !self.grounded() {
+            self.lock_delay = None;
+            return
+        }
+        let resting = self.lock_delay.map(|ms| ms + dt).unwrap_or(0.0);
+        if resting >= LOCK_DELAY_MS {
+            self.lock()
+        } else {
+            self.lock_delay = Some(resting)
+        }
+    }
+
+    /// The current piece was moved or rotated, which restarts the lock delay
+    /// a limited number of times per row.
+    fn moved(&mut self) {
+        if self.current.y_offset > self.lowest_row {
+            self.lowest_row = self.current.y_offset;
+            self.lock_resets = 0;
+        }
+        if self.lock_delay.is_some() && self.lock_resets < MAX_LOCK_RESETS {
+            self.lock_delay = Some(0.0);
+            self.lock_resets += 1;
+        }
+    }
+
+    /// Where the current piece would land if it was dropped straight down.
+    pub fn drop_position(&self) -> Piece {
+        let mut prev_move = self.current;
+        let mut next_move = self.current.move_down();
+        while !self.collide(next_move.coords()) {
+            prev_move = next_move;
+            next_move = next_move.move_down();
+        }
+        prev_move
+    }
+
+    /// Drop the current piece straight down and lock it in place at once.
+    fn hard_drop(&mut self) {
+        if self.over() || self.pause {
+            return
+        }
+        let drop = self.drop_position();
+        // Falling after a rotation means the piece wasn't spun into place
+        if drop.y_offset != self.current.y_offset {
+            self.last_rotation = None;
+        }
+        self.score += 2 * (drop.y_offset - self.current.y_offset) as usize;
+        self.current = drop;
+        self.lock()
+    }
+
+    /// Add the current piece to the board and bring in the next one.
+    fn lock(&mut self) {
+        let t_spin = self.t_spin();
+        if !self.soft_dropped && self.piece_inputs > finesse::min_inputs(self.current) {
+            self.finesse_faults += 1;
+        }
+        self.pieces += 1;
+        let color = self.current.color();
+        let coords = self.current.coords();
+        // Pieces can rotate up past the top, but locking there tops out
+        if coords.iter().any(|coord| coord.y < 0) {
+            self.lost = true;
+            return
+        }
+        for coord in coords.iter() {
+            self.board[coord.y as usize][coord.x as usize] = Some(color);
+        }
+        let rows = self.clear_full_rows();
+        let attack = self.attack_for(rows, t_spin);
+        self.score_clear(rows, t_spin);
+        if rows == 0 {
+            self.raise_garbage();
+            if self.lost {
+                return
+            }
+        } else {
+            // Clearing lines cancels out garbage before any is sent back
+            let cancelled = attack.min(self.pending_garbage);
+            self.pending_garbage -= cancelled;
+            self.attack += attack - cancelled;
+            self.garbage_sent += attack - cancelled;
+        }
+        let goal_reached = match self.mode {
+            Mode::Sprint => self.lines >= SPRINT_LINES,
+            Mode::Dig => self.garbage_rows() == 0,
+            _ => false
+        };
+        if goal_reached {
+            self.finished = true;
+            return
+        }
+        self.can_hold = true;
+        let next = self.take_next();
+        self.spawn(next);
+    }
+
+    /// Swap the current piece into the hold slot.
+    ///
+    /// The held piece comes back out, or the next piece if nothing was held.
+    /// This can only be done once until the current piece is locked.
+    fn hold(&mut self) {
+        if !self.can_hold {
+            return
+        }
+        let held = self.current.stash();
+        let piece = match self.hold.replace(held) {
+            Some(piece) => piece,
+            None => self.take_next()
+        };
+        self.spawn(piece);
+        self.can_hold = false;
+    }
+
+    /// Take the teased piece, and deal a new one to replace it.
+    fn take_next(&mut self) -> Piece {
+        let next = Piece::random(&mut self.bag, &mut self.rng).tease();
+        std::mem::replace(&mut self.next, next)
+    }
+
+    /// Make the piece the falling one, losing if there's no room for it.
+    fn spawn(&mut self, piece: Piece) {
+        self.current = piece.reset();
+        self.fall_progress = 0.0;
+        self.lock_delay = None;
+        self.lock_resets = 0;
+        self.last_rotation = None;
+        self.lowest_row = self.current.y_offset;
+        self.piece_inputs = 0;
+        self.soft_dropped = self.soft_drop;
+        if self.collide(self.current.coords()) {
+            self.lost = true;
+        }
+    }
+
+    /// Determines if a T piece is being locked after spinning into place.
+    ///
+    /// This uses the 3-corner rule: at least three of the corners around the
+    /// center of the T have to be filled. It's a full T-spin when both corners
+    /// the T points towards are filled, or when it took the last wall kick to
+    /// get in, otherwise it's a mini.
+    fn t_spin(&self) -> TSpin {
+        let kick = match (self.current.ty, self.last_rotation) {
+            (PieceType::T, Some(kick)) => kick,
+            _ => return TSpin::None
+        };
+        let x = self.current.x_offset + 1;
+        let y = self.current.y_offset + 1;
+        // Clockwise from the top left
+        let corners = [self.occupied(x - 1, y - 1),
+                       self.occupied(x + 1, y - 1),
+                       self.occupied(x + 1, y + 1),
+                       self.occupied(x - 1, y + 1)];
+        if corners.iter().filter(|&&filled| filled).count() < 3 {
+            return TSpin::None
+        }
+        let front = match self.current.rotation {
+            Rotation::Spawn => corners[0] && corners[1],
+            Rotation::Right => corners[1] && corners[2],
+            Rotation::Flip => corners[2] && corners[3],
+            Rotation::Left => corners[3] && corners[0]
+        };
+        if front || kick == 4 {
+            TSpin::Full
+        } else {
+            TSpin::Mini
+        }
+    }
+
+    /// Determines if a cell is filled, counting the walls and floor as filled
+    /// and anything above the board as empty.
+    fn occupied(&self, x: i32, y: i32) -> bool {
+        if x < 0 || x >= BOARD_WIDTH as i32 || y >= BOARD_HEIGHT as i32 {
+            return true
+        }
+        y >= 0 && self.board[y as usize][x as usize].is_some()
+    }
+
+    /// Count down how long the last callout has been shown for.
+    fn step_callout(&mut self, dt: f64) {
+        let expired = match self.callout {
+            Some((_, ref mut ms)) => {
+                *ms -= dt;
+                *ms <= 0.0
+            },
+            None => false
+        };
+        if expired {
+            self.callout = None;
+        }
+    }
+
+    /// Determines if the next step collides it the board with a piece
+    fn collide(&self, next: [Point; 4]) -> bool {
+        next.iter().any(|coord| self.occupied(coord.x, coord.y))
+    }
+
+    /// Clear any full rows that exist, returning how many there were.
+    fn clear_full_rows(&mut self) -> usize {
+        let mut rows = vec![];
+        'row_check: for (index, row) in self.board.iter_mut().enumerate() {
+            for block in row.iter_mut() {
+                if block.is_none() {
+                    continue 'row_check
+                }
+            }
+            rows.push(index);
+            *row = [None; BOARD_WIDTH]
+        }
+        for &row_index in &rows {
+            let mut prev_index = row_index;
+            for above_index in (0..(row_index + 1)).rev() {
+                self.board[prev_index] = self.board[above_index];
+                prev_index = above_index;
+            }
+        }
+        rows.len()
+    }
+
+    /// Push the pending garbage up from the bottom of the board, losing if
+    /// the stack gets pushed out of the top.
+    ///
+    /// Garbage that arrives together has its holes lined up.
+    fn raise_garbage(&mut self) {
+        let rows = std::mem::replace(&mut self.pending_garbage, 0).min(BOARD_HEIGHT);
+        if rows == 0 {
+            return
+        }
+        if self.board[..rows].iter().any(|row| row.iter().any(Option::is_some)) {
+            self.lost = true;
+        }
+        for index in 0..(BOARD_HEIGHT - rows) {
+            self.board[index] = self.board[index + rows];
+        }
+        let hole = self.garbage_rng.gen_range(0, BOARD_WIDTH);
+        for row in self.board.iter_mut().rev().take(rows) {
+            *row = garbage_row(hole);
+        }
+    }
+
+    /// How many rows of garbage a clear sends, following the guideline
+    /// attack table.
+    ///
+    /// This has to be worked out before `score_clear` moves the combo and
+    /// back-to-back along.
+    fn attack_for(&self, rows: usize, t_spin: TSpin) -> usize {
+        if rows == 0 {
+            return 0
+        }
+        let mut attack = match (t_spin, rows) {
+            (TSpin::None, 4) => 4,
+            (TSpin::None, rows) => rows - 1,
+            (TSpin::Mini, rows) => rows - 1,
+            (TSpin::Full, rows) => 2 * rows
+        };
+        let difficult = rows >= 4 || t_spin != TSpin::None;
+        if difficult && self.back_to_back {
+            attack += 1;
+        }
+        let combo = self.combo.map(|combo| combo + 1).unwrap_or(0);
+        attack + COMBO_ATTACK[combo.min(COMBO_ATTACK.len() - 1)]
+    }
+
+    /// Award points for the rows cleared by locking a piece.
+    ///
+    /// Clearing with consecutive pieces builds a combo, and a difficult clear
+    /// (a Tetris or a T-spin) right after another one earns a back-to-back
+    /// bonus.
+    fn score_clear(&mut self, rows: usize, t_spin: TSpin) {
+        let mut points = match (t_spin, rows) {
+            (TSpin::None, 0) => 0,
+            (TSpin::None, 1) => 100,
+            (TSpin::None, 2) => 300,
+            (TSpin::None, 3) => 500,
+            (TSpin::None, _) => 800,
+            (TSpin::Mini, 0) => 100,
+            (TSpin::Mini, 1) => 200,
+            (TSpin::Mini, _) => 400,
+            (TSpin::Full, 0) => 400,
+            (TSpin::Full, 1) => 800,
+            (TSpin::Full, 2) => 1200,
+            (TSpin::Full, _) => 1600
+        } * self.level;
+        let mut callout = clear_name(rows, t_spin);
+        if rows == 0 {
+            self.combo = None;
+        } else {
+            let difficult = rows >= 4 || t_spin != TSpin::None;
+            if difficult && self.back_to_back {
+                points = points * 3 / 2;
+                callout = callout.map(|callout| format!("Back-to-Back {}", callout));
+            }
+            self.back_to_back = difficult;
+            let combo = self.combo.map(|combo| combo + 1).unwrap_or(0);
+            points += 50 * combo * self.level;
+            self.combo = Some(combo);
+        }
+        if let Some(callout) = callout {
+            self.callout = Some((callout, CALLOUT_MS));
+        }
+        self.score += points;
+        self.lines += rows;
+        self.level = self.level.max(1 + self.lines / LINES_PER_LEVEL).min(MAX_LEVEL);
+    }
+}
+
+/// The name to call out for a clear, if it's a special one.
+fn clear_name(rows: usize, t_spin: TSpin) -> Option<String> {
+    let rows_name = match rows {
+        0 => "",
+        1 => " Single",
+        2 => " Double",
+        3 => " Triple",
+        _ => return Some("Tetris".into())
+    };
+    match t_spin {
+        TSpin::None => None,
+        TSpin::Mini => Some(format!("Mini T-Spin{}", rows_name)),
+        TSpin::Full => Some(format!("T-Spin{}", rows_name))
+    }
+}
+
+/// A random number generator that always gives the same numbers for a seed.
+fn seeded_rng(seed: u64) -> XorShiftRng {
+    let (low, high) = (seed as u32, (seed >> 32) as u32);
+    // XorShift can't start from all zeroes, so mix in some arbitrary bits
+    XorShiftRng::from_seed([low ^ 0x9E37_79B9,
+                            high ^ 0x7F4A_7C15,
+                            low ^ 0xF39C_C060,
+                            high ^ 0x5CED_C834])
+}
+
+/// Fill the bottom rows of the board with garbage, leaving one hole in each
+/// row.
+fn fill_garbage<R: Rng>(board: &mut [[Option<Color>; BOARD_WIDTH]; BOARD_HEIGHT],
+                        rows: usize,
+                        rng: &mut R) {
+    for row in board.iter_mut().rev().take(rows) {
+        *row = garbage_row(rng.gen_range(0, BOARD_WIDTH));
+    }
+}
+
+/// A full row of garbage, except for the hole.
+fn garbage_row(hole: usize) -> [Option<Color>; BOARD_WIDTH] {
+    let mut row = [Some(Color::garbage()); BOARD_WIDTH];
+    row[hole] = None;
+    row
+}
+
+/// How many rows a piece falls each millisecond at the given level.
+///
+/// This follows the guideline curve, where a row takes
+/// `(0.8 - (level - 1) * 0.007) ^ (level - 1)` seconds, up until 20G.
+fn gravity(level: usize) -> f64 {
+    if level >= MAX_LEVEL {
+        return TWENTY_G
+    }
+    let level = (level - 1) as f64;
+    let seconds_per_row = (0.8 - level * 0.007).powf(level);
+    (1.0 / (seconds_per_row * 1000.0)).min(TWENTY_G)
+}
+
+/// The number of milliseconds in a duration.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1000000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How long the compositor steps the game by at a time.
+    fn tick() -> Duration {
+        Duration::new(0, 1000000000 / 60)
+    }
+
+    fn game() -> Tetris {
+        Tetris::new(Mode::Marathon, Handling::default(), 7)
+    }
+
+    /// Press and let go of a key straight away, without letting time pass.
+    fn tap(tetris: &mut Tetris, action: Action) {
+        tetris.step(Duration::from_secs(0), &[Input::Press(action), Input::Release(action)]);
+    }
+
+    fn wait(tetris: &mut Tetris, ticks: usize) {
+        for _ in 0..ticks {
+            tetris.step(tick(), &[]);
+        }
+    }
+
+    /// Make a piece the falling one, right where it would land.
+    fn land(tetris: &mut Tetris, piece: Piece) {
+        tetris.spawn(piece);
+        tetris.current = piece;
+        tetris.current = tetris.drop_position();
+    }
+
+    /// An upright I piece over a column.
+    fn upright_i(column: i32) -> Piece {
+        let mut piece = Piece::new(PieceType::I).rotate(Dir::Right);
+        piece.x_offset = column - 2;
+        piece
+    }
+
+    /// Fill the bottom rows of the board, leaving a hole in each.
+    fn fill(tetris: &mut Tetris, rows: usize, hole: usize) {
+        for row in tetris.board.iter_mut().rev().take(rows) {
+            *row = [Some(Color::Red); BOARD_WIDTH];
+            row[hole] = None;
+        }
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let play = |seed| {
+            let mut tetris = Tetris::new(Mode::Marathon, Handling::default(), seed);
+            let mut dealt = vec![];
+            for turn in 0..30 {
+                dealt.push(tetris.current.ty);
+                let action = if turn % 3 == 0 { Action::Left } else { Action::RotateRight };
+                tap(&mut tetris, action);
+                wait(&mut tetris, 5);
+                tap(&mut tetris, Action::HardDrop);
+            }
+            (dealt, tetris.board, tetris.score)
+        };
+        assert!(play(42) == play(42));
+        assert!(play(42).0 != play(43).0);
+    }
+
+    #[test]
+    fn each_ultra_length_has_its_own_table() {
+        assert_eq!(Mode::Ultra(ULTRA_SECONDS).table_name(), "ultra-120");
+        assert!(Mode::Ultra(60).table_name() != Mode::Ultra(120).table_name());
+        assert_eq!(Mode::Sprint.table_name(), "sprint");
+    }
+
+    #[test]
+    fn gravity_follows_the_guideline_curve() {
+        assert!((1.0 / gravity(1) - 1000.0).abs() < 1e-6);
+        assert!((1.0 / gravity(10) - 1000.0 * 0.737f64.powi(9)).abs() < 1e-6);
+        assert_eq!(gravity(MAX_LEVEL), TWENTY_G);
+        for level in 1..MAX_LEVEL {
+            assert!(gravity(level) <= gravity(level + 1));
+        }
+    }
+
+    #[test]
+    fn pieces_fall_with_gravity() {
+        let mut tetris = game();
+        let start = tetris.current.y_offset;
+        // A row every second at level 1
+        wait(&mut tetris, 59);
+        assert_eq!(tetris.current.y_offset, start);
+        wait(&mut tetris, 2);
+        assert_eq!(tetris.current.y_offset, start + 1);
+        // Straight to the floor at 20G
+        tetris.level = MAX_LEVEL;
+        wait(&mut tetris, 1);
+        assert_eq!(tetris.current.y_offset, tetris.drop_position().y_offset);
+    }
+
+    #[test]
+    fn pieces_lock_after_resting() {
+        let mut tetris = game();
+        land(&mut tetris, Piece::new(PieceType::Block));
+        wait(&mut tetris, 30);
+        assert_eq!(tetris.pieces, 0);
+        wait(&mut tetris, 10);
+        assert_eq!(tetris.pieces, 1);
+    }
+
+    #[test]
+    fn moving_restarts_the_lock_delay() {
+        let mut tetris = game();
+        land(&mut tetris, Piece::new(PieceType::Block));
+        wait(&mut tetris, 20);
+        tap(&mut tetris, Action::Left);
+        wait(&mut tetris, 20);
+        assert_eq!(tetris.pieces, 0);
+        wait(&mut tetris, 20);
+        assert_eq!(tetris.pieces, 1);
+    }
+
+    #[test]
+    fn lock_delay_resets_run_out() {
+        let mut tetris = game();
+        land(&mut tetris, Piece::new(PieceType::Block));
+        let shuffle = |tetris: &mut Tetris, times| {
+            for turn in 0..times {
+                wait(tetris, 10);
+                tap(tetris, if turn % 2 == 0 { Action::Left } else { Action::Right });
+            }
+        };
+        shuffle(&mut tetris, MAX_LOCK_RESETS);
+        assert_eq!(tetris.pieces, 0);
+        // Moving doesn't hold the piece up anymore
+        shuffle(&mut tetris, 4);
+        assert_eq!(tetris.pieces, 1);
+    }
+
+    #[test]
+    fn garbage_rises_with_lined_up_holes() {
+        let mut tetris = game();
+        tetris.receive_garbage(2);
+        land(&mut tetris, Piece::new(PieceType::Block));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.pending_garbage, 0);
+        assert_eq!(tetris.garbage_rows(), 2);
+        let holes: Vec<_> = tetris.board[BOARD_HEIGHT - 2..]
+            .iter()
+            .map(|row| row.iter().position(Option::is_none))
+            .collect();
+        assert!(holes[0].is_some());
+        assert_eq!(holes[0], holes[1]);
+        // The stack was pushed up on top of it
+        assert!(tetris.board[BOARD_HEIGHT - 3].contains(&Some(Color::Blue)));
+        assert!(!tetris.lost);
+    }
+
+    #[test]
+    fn garbage_pushing_the_stack_out_loses() {
+        let mut tetris = game();
+        tetris.board[1][0] = Some(Color::Red);
+        tetris.receive_garbage(2);
+        land(&mut tetris, Piece::new(PieceType::Block));
+        tap(&mut tetris, Action::HardDrop);
+        assert!(tetris.lost);
+    }
+
+    #[test]
+    fn clears_cancel_garbage_before_sending_it() {
+        let mut tetris = game();
+        fill(&mut tetris, 4, 0);
+        tetris.receive_garbage(3);
+        land(&mut tetris, upright_i(0));
+        tap(&mut tetris, Action::HardDrop);
+        // A Tetris sends 4 rows, 3 of which cancel what was on the way
+        assert_eq!(tetris.pending_garbage, 0);
+        assert_eq!(tetris.garbage_rows(), 0);
+        assert_eq!(tetris.take_attack(), 1);
+        assert_eq!(tetris.take_attack(), 0);
+        assert_eq!(tetris.garbage_sent, 1);
+    }
+
+    /// The score for dropping an upright I into a well with the bottom rows
+    /// filled around it.
+    fn clear_score(rows: usize, level: usize) -> usize {
+        let mut tetris = game();
+        tetris.level = level;
+        fill(&mut tetris, rows, 0);
+        land(&mut tetris, upright_i(0));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.lines, rows);
+        tetris.score
+    }
+
+    #[test]
+    fn clears_score_by_rows_and_level() {
+        assert_eq!(clear_score(1, 1), 100);
+        assert_eq!(clear_score(2, 1), 300);
+        assert_eq!(clear_score(3, 1), 500);
+        assert_eq!(clear_score(4, 1), 800);
+        assert_eq!(clear_score(1, 3), 300);
+        assert_eq!(clear_score(4, 2), 1600);
+    }
+
+    #[test]
+    fn combos_add_up() {
+        let mut tetris = game();
+        fill(&mut tetris, 2, 0);
+        tetris.board[BOARD_HEIGHT - 2][BOARD_WIDTH - 1] = None;
+        land(&mut tetris, upright_i(0));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.score, 100);
+        land(&mut tetris, upright_i(BOARD_WIDTH as i32 - 1));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.score, 100 + 100 + 50);
+        assert_eq!(tetris.combo, Some(1));
+        // Locking without clearing anything breaks the combo
+        land(&mut tetris, Piece::new(PieceType::Block));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.combo, None);
+    }
+
+    #[test]
+    fn back_to_back_tetrises_earn_a_bonus() {
+        let mut tetris = game();
+        fill(&mut tetris, 8, 0);
+        land(&mut tetris, upright_i(0));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.score, 800);
+        land(&mut tetris, upright_i(0));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.score, 800 + 1200 + 50);
+        assert_eq!(tetris.callout.as_ref().map(|callout| &callout.0[..]),
+                   Some("Back-to-Back Tetris"));
+    }
+
+    #[test]
+    fn singles_break_back_to_back() {
+        let mut tetris = game();
+        fill(&mut tetris, 9, 0);
+        tetris.board[BOARD_HEIGHT - 5][0] = Some(Color::Red);
+        tetris.board[BOARD_HEIGHT - 5][1] = None;
+        // A Tetris, then a single, then another Tetris
+        land(&mut tetris, upright_i(0));
+        tap(&mut tetris, Action::HardDrop);
+        land(&mut tetris, upright_i(1));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.lines, 5);
+        let before = tetris.score;
+        land(&mut tetris, upright_i(0));
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.lines, 9);
+        assert_eq!(tetris.score - before, 800 + 2 * 50);
+    }
+
+    #[test]
+    fn drops_earn_points() {
+        let mut tetris = game();
+        let fall = tetris.drop_position().y_offset - tetris.current.y_offset;
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.score, 2 * fall as usize);
+        let mut tetris = game();
+        let start = tetris.current.y_offset;
+        tetris.step(Duration::from_secs(0), &[Input::Press(Action::SoftDrop)]);
+        wait(&mut tetris, 30);
+        let fallen = tetris.current.y_offset - start;
+        assert!(fallen > 0);
+        assert_eq!(tetris.score, fallen as usize);
+    }
+
+    /// A T piece, pointing to the right, in the mouth of a T-spin double slot
+    /// under an overhang.
+    fn t_spin_double_slot(tetris: &mut Tetris) -> Piece {
+        fill(tetris, 2, 4);
+        tetris.board[BOARD_HEIGHT - 2][3] = None;
+        tetris.board[BOARD_HEIGHT - 2][5] = None;
+        tetris.board[BOARD_HEIGHT - 3][3] = Some(Color::Red);
+        let mut piece = Piece::new(PieceType::T).rotate(Dir::Right);
+        piece.x_offset = 3;
+        piece
+    }
+
+    fn callout(tetris: &Tetris) -> Option<&str> {
+        tetris.callout.as_ref().map(|callout| &callout.0[..])
+    }
+
+    #[test]
+    fn t_spin_double() {
+        let mut tetris = game();
+        let piece = t_spin_double_slot(&mut tetris);
+        land(&mut tetris, piece);
+        tap(&mut tetris, Action::RotateRight);
+        assert_eq!(tetris.t_spin(), TSpin::Full);
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.lines, 2);
+        assert_eq!(tetris.score, 1200);
+        assert_eq!(tetris.take_attack(), 4);
+        assert_eq!(callout(&tetris), Some("T-Spin Double"));
+    }
+
+    #[test]
+    fn t_spin_mini() {
+        // Only one of the corners the T points towards is filled
+        let mut tetris = game();
+        let piece = t_spin_double_slot(&mut tetris);
+        land(&mut tetris, piece);
+        tap(&mut tetris, Action::RotateLeft);
+        assert_eq!(tetris.t_spin(), TSpin::Mini);
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.lines, 1);
+        assert_eq!(tetris.score, 200);
+        assert_eq!(callout(&tetris), Some("Mini T-Spin Single"));
+    }
+
+    #[test]
+    fn t_spins_need_the_last_move_to_be_a_rotation() {
+        // Dropped straight in
+        let mut tetris = game();
+        let piece = t_spin_double_slot(&mut tetris);
+        land(&mut tetris, piece);
+        assert_eq!(tetris.t_spin(), TSpin::None);
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.score, 100);
+        assert_eq!(callout(&tetris), None);
+        // Rotated higher up, then dropped in
+        let mut tetris = game();
+        let mut piece = t_spin_double_slot(&mut tetris);
+        piece.y_offset -= 3;
+        tetris.current = piece;
+        tap(&mut tetris, Action::RotateLeft);
+        tap(&mut tetris, Action::RotateRight);
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(callout(&tetris), None);
+    }
+
+    #[test]
+    fn rotations_dont_carry_over_to_the_next_piece() {
+        let mut scratch = game();
+        let piece = t_spin_double_slot(&mut scratch);
+        land(&mut scratch, piece);
+        tap(&mut scratch, Action::RotateRight);
+        let in_slot = scratch.current;
+        // A J rotates and locks out of the way, then the T is already in the
+        // slot when it comes in
+        let mut tetris = game();
+        t_spin_double_slot(&mut tetris);
+        let mut j = Piece::new(PieceType::J);
+        j.x_offset = 6;
+        land(&mut tetris, j);
+        tap(&mut tetris, Action::RotateRight);
+        assert!(tetris.last_rotation.is_some());
+        wait(&mut tetris, 60);
+        assert_eq!(tetris.pieces, 1);
+        tetris.current = in_slot;
+        tap(&mut tetris, Action::HardDrop);
+        assert_eq!(tetris.lines, 2);
+        assert_eq!(tetris.score, 300);
+        assert_eq!(callout(&tetris), None);
+    }
+
+    #[test]
+    fn last_kick_is_always_a_full_t_spin() {
+        let mut tetris = game();
+        tetris.current = Piece::new(PieceType::T).rotate(Dir::Right);
+        tetris.current.x_offset = 0;
+        tetris.current.y_offset = BOARD_HEIGHT as i32 - 3;
+        tetris.last_rotation = Some(4);
+        // Three corners, but not the two the T points towards
+        tetris.board[BOARD_HEIGHT - 3][0] = Some(Color::Red);
+        tetris.board[BOARD_HEIGHT - 1][0] = Some(Color::Red);
+        tetris.board[BOARD_HEIGHT - 1][2] = Some(Color::Red);
+        assert_eq!(tetris.t_spin(), TSpin::Full);
+        tetris.last_rotation = Some(1);
+        assert_eq!(tetris.t_spin(), TSpin::Mini);
+    }
+}
@@ -0,0 +1,24 @@
+//! The rules of Tetris, without any rendering, input devices or clocks.
+//!
+//! A game only moves forward when `Tetris::step` is called, so it can be
+//! driven by the compositor, by tests or by bots alike.
+
+extern crate rand;
+
+mod bag;
+mod color;
+mod finesse;
+mod game;
+mod piece;
+mod replay;
+#[cfg(test)]
+mod temp_dir;
+
+pub use color::Color;
+pub use game::{Action, Handling, Input, Mode, DIG_ROWS, SPRINT_LINES, TSpin, Tetris,
+               ULTRA_SECONDS};
+pub use piece::{Dir, Piece, PieceData, PieceType, Point, Rotation};
+pub use replay::{Playback, Replay};
+
+pub const BOARD_WIDTH: usize = 10;
+pub const BOARD_HEIGHT: usize = 20;
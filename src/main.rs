@@ -2,606 +2,971 @@
 
 extern crate rand;
 extern crate rusttype;
+extern crate tetris_wlroots;
+extern crate toml;
 #[macro_use]
 extern crate wlroots;
 
-use rand::{Rand, Rng, random};
-use rusttype::{Font, Scale};
-use std::time::Instant;
+mod bindings;
+mod menu;
+mod scores;
+#[cfg(test)]
+mod temp_dir;
+mod text;
+
+use bindings::{Bindings, Command, Seat};
+use menu::{Entry, Menu, Page, mode_title};
+use rusttype::Font;
+use scores::Record;
+use std::os::raw::{c_int, c_void};
+use std::path::PathBuf;
+use std::ptr;
+use std::time::{Duration, Instant};
+use text::{Align, Text};
+use tetris_wlroots::{Action, BOARD_HEIGHT, BOARD_WIDTH, Color, Handling, Input, Mode, Playback,
+                     Point, Replay, DIG_ROWS, SPRINT_LINES, Tetris, ULTRA_SECONDS};
 
 use wlroots::{Area, CompositorBuilder, CompositorHandle, InputManagerHandler, KeyboardHandle,
               KeyboardHandler, Origin, OutputBuilder, OutputBuilderResult, OutputHandle,
-              OutputHandler, OutputManagerHandler, Size, key_events::KeyEvent,
-              xkbcommon::xkb::{KEY_Down, KEY_Escape, KEY_Left, KEY_Right, KEY_x, KEY_z,
-                               KEY_r, KEY_space},
-              WLR_KEY_PRESSED};
+              OutputHandler, OutputManagerHandler, Renderer, Size, key_events::KeyEvent,
+              WLR_KEY_PRESSED,
+              wlroots_sys::wayland_server::ffi::{WAYLAND_SERVER_HANDLE, wl_event_source}};
 
-compositor_data!(Tetris);
+compositor_data!(State);
 
-const BOARD_WIDTH: usize = 10;
-const BOARD_HEIGHT: usize = 20;
 const BOARD_WIDTH_EDGE: usize = BOARD_WIDTH + 1;
 const BOARD_HEIGHT_EDGE: usize = BOARD_HEIGHT + 1;
 
-#[derive(Debug, Clone, Copy)]
-enum Color {
-    Blue,
-    Purple,
-    Orange,
-    Yellow,
-    Red,
-    Grey,
-    DarkGrey,
-    Green,
-    Pink,
-    TransparentRed,
-    TransparentBlue
+/// How many nanoseconds the game is stepped by at a time, 60 times a second.
+const TICK_NANOS: u32 = 1000000000 / 60;
+/// How many milliseconds each press changes the DAS setting by.
+const DAS_STEP: u64 = 10;
+/// How many milliseconds each press changes the ARR setting by.
+const ARR_STEP: u64 = 5;
+
+#[derive(Default, Clone, Copy)]
+struct Handler;
+
+/// Someone playing a game, with their own keys to play it with.
+struct Player {
+    tetris: Tetris,
+    /// Where the player sits, which decides the keys they play with.
+    seat: Seat,
+    /// Inputs waiting to be given to the game on the next tick.
+    inputs: Vec<Input>,
+    /// Every input given to the current game so far.
+    replay: Replay,
+    /// Whether the game has been put on the high score table yet, which is
+    /// done once it's ended.
+    recorded: bool,
+    /// Where the game placed on the high score table, if it made it on.
+    place: Option<usize>,
+    /// The best record for the mode from before the game was put on the
+    /// table.
+    previous_best: Option<Record>
 }
 
-#[derive(Default, Debug, Clone, Copy)]
-struct PieceData(Origin, Origin, Origin, Origin);
+impl Player {
+    fn new(tetris: Tetris, seat: Seat) -> Self {
+        Player { replay: Replay::of(&tetris),
+                 tetris,
+                 seat,
+                 inputs: Vec::new(),
+                 recorded: false,
+                 place: None,
+                 previous_best: None }
+    }
 
-impl PieceData {
-    fn center(&self) -> Origin {
-        let mut sum = Origin::new(0, 0);
-        for point in [self.0, self.1, self.2, self.3].iter() {
-            sum.x += point.x;
-            sum.y += point.y;
+    /// Put the game on the high score table, if it's good enough.
+    fn record(&mut self) {
+        if self.recorded {
+            return
+        }
+        self.recorded = true;
+        let record = match Record::of(&self.tetris) {
+            Some(record) => record,
+            None => return
+        };
+        let mode = self.tetris.mode;
+        self.previous_best = scores::load(mode).first().cloned();
+        match scores::add(mode, record) {
+            Ok(place) => self.place = place,
+            Err(err) => eprintln!("Could not save high score: {}", err)
         }
-        return Origin::new((sum.x + 2) / 4, (sum.y + 2) / 4);
     }
 }
 
-#[derive(Clone, Copy)]
-enum PieceType {
-    Block,
-    L,
-    I,
-    J,
-    T,
-    S,
-    Z
+/// A game shown on one output, played by one or more players.
+struct Game {
+    /// Everyone playing, from left to right.
+    players: Vec<Player>,
+    /// The seed every game starts from, if one was chosen.
+    seed: Option<u64>,
+    /// Where to save the replay of each game, if anywhere.
+    record: Option<PathBuf>,
+    /// The replay being played back instead of taking inputs, if any.
+    playback: Option<Playback>,
+    /// The menu showing over the game, if any.
+    menu: Option<Menu>
 }
 
-impl PieceType {
-    fn origin(self) -> PieceData {
-        use PieceType::*;
-        match self {
-            S => {
-                let a = Origin::new(0, 1);
-                let b = Origin::new(1, 1);
-                let c = Origin::new(1, 0);
-                let d = Origin::new(2, 0);
-                PieceData(a, b, c, d)
-
+impl Game {
+    fn new(mode: Mode,
+           handling: Handling,
+           seed: Option<u64>,
+           record: Option<PathBuf>,
+           playback: Option<Playback>)
+           -> Self {
+        // A replay starts playing straight away, anything else starts at
+        // the title screen
+        let (tetris, menu) = match playback {
+            Some(ref playback) => (playback.replay.game(), None),
+            None => {
+                let tetris = Tetris::new(mode, handling, seed.unwrap_or_else(rand::random));
+                (tetris, Some(Menu::title()))
             }
-            Block => {
-                let a = Origin::new(0, 0);
-                let b = Origin::new(1, 0);
-                let c = Origin::new(0, 1);
-                let d = Origin::new(1, 1);
-                PieceData(a, b, c, d)
+        };
+        Game { players: players(tetris),
+               seed,
+               record,
+               playback,
+               menu }
+    }
 
+    /// Start a new game of a mode.
+    fn start(&mut self, mode: Mode, handling: Handling) {
+        self.end();
+        let seed = self.seed.unwrap_or_else(rand::random);
+        self.players = players(Tetris::new(mode, handling, seed));
+        self.menu = None;
+    }
+
+    /// Start a new game of the same mode, or start the replay over if one is
+    /// playing.
+    fn restart(&mut self, handling: Handling) {
+        let mode = self.players[0].tetris.mode;
+        match self.playback {
+            Some(ref mut playback) => {
+                playback.rewind();
+                self.players = players(playback.replay.game());
             },
-            L => {
-                let a = Origin::new(0, 0);
-                let b = Origin::new(0, 1);
-                let c = Origin::new(0, 2);
-                let d = Origin::new(1, 2);
-                PieceData(a, b, c, d)
-            },
-            I => {
-                let a = Origin::new(0, 0);
-                let b = Origin::new(0, 1);
-                let c = Origin::new(0, 2);
-                let d = Origin::new(0, 3);
-                PieceData(a, b, c, d)
-            },
-            J => {
-                let a = Origin::new(1, 0);
-                let b = Origin::new(1, 1);
-                let c = Origin::new(1, 2);
-                let d = Origin::new(0, 2);
-                PieceData(a, b, c, d)
-            },
-            T => {
-                let a = Origin::new(0, 1);
-                let b = Origin::new(1, 1);
-                let c = Origin::new(1, 0);
-                let d = Origin::new(2, 1);
-                PieceData(a, b, c, d)
-            },
-            Z => {
-                let a = Origin::new(0, 0);
-                let b = Origin::new(1, 0);
-                let c = Origin::new(1, 1);
-                let d = Origin::new(2, 1);
-                PieceData(a, b, c, d)
-            }
+            None => self.start(mode, handling)
         }
     }
-}
 
-impl Rand for PieceType {
-    fn rand<R: Rng>(rng: &mut R) -> Self {
-        use PieceType::*;
-        match rng.gen_range(0, 7) {
-            0 => L,
-            1 => Block,
-            2 => I,
-            3 => J,
-            4 => T,
-            5 => S,
-            _ => Z
+    /// Pause everyone's game and show the pause menu.
+    fn pause(&mut self) {
+        for player in &mut self.players {
+            player.inputs.push(Input::Press(Action::Pause));
+            player.inputs.push(Input::Release(Action::Pause));
         }
+        self.menu = Some(Menu::pause());
     }
-}
 
-#[derive(Clone, Copy)]
-struct Piece {
-    data: PieceData,
-    x_offset: i32,
-    y_offset: i32,
-    center: Origin,
-    ty: PieceType
-}
+    /// Close the pause menu and carry on playing.
+    fn resume(&mut self) {
+        for player in &mut self.players {
+            player.inputs.push(Input::Press(Action::Pause));
+            player.inputs.push(Input::Release(Action::Pause));
+        }
+        self.menu = None;
+    }
 
-impl Piece {
-    fn random() -> Self {
-        let ty: PieceType = random();
-        let data = ty.origin();
-        let center = data.center();
-        let x_offset = BOARD_WIDTH as i32 / 2 - center.x;
-        Piece { ty, x_offset, y_offset: 0, center, data }
+    /// Whether the game has ended, and is waiting to be started again.
+    fn over(&self) -> bool {
+        self.players.iter().any(|player| player.tetris.over())
     }
 
-    /// Put the piece floating to the top right
-    fn tease(mut self) -> Self {
-        self.x_offset = (BOARD_WIDTH + 2) as i32 - self.center.x / 2;
-        self.y_offset = 0;
-        self
+    /// Step each player's game forward by one tick, with the inputs that came
+    /// in since the last one, and pass the garbage they send between them.
+    fn tick(&mut self) {
+        // Nothing is being played behind the menus, except while paused
+        if self.menu.as_ref().map_or(false, |menu| menu.page != Page::Pause) {
+            return
+        }
+        let tick = Duration::new(0, TICK_NANOS);
+        let was_over = self.over();
+        for player in &mut self.players {
+            let inputs = match self.playback {
+                Some(ref mut playback) => playback.inputs(player.tetris.ticks),
+                None => std::mem::take(&mut player.inputs)
+            };
+            for &input in &inputs {
+                player.replay.record(player.tetris.ticks, input);
+            }
+            player.tetris.step(tick, &inputs);
+        }
+        let attacks: Vec<usize> =
+            self.players.iter_mut().map(|player| player.tetris.take_attack()).collect();
+        let total: usize = attacks.iter().sum();
+        for (player, attack) in self.players.iter_mut().zip(attacks) {
+            player.tetris.receive_garbage(total - attack);
+        }
+        // The last one standing wins a versus game
+        if self.players.len() > 1 && self.players.iter().any(|player| player.tetris.lost) {
+            for player in &mut self.players {
+                player.tetris.finished = !player.tetris.lost;
+            }
+        }
+        if self.over() && !was_over {
+            self.end();
+        }
     }
 
-    /// Reset to the center of the screen.
-    fn reset(mut self) -> Self{
-        self.x_offset = BOARD_WIDTH as i32 / 2 - self.center.x;
-        self.y_offset = 0;
-        self
+    /// Save the replay and put everyone on the high score table, once the
+    /// game is over or has been given up on.
+    fn end(&mut self) {
+        self.save_replay();
+        if self.playback.is_none() {
+            for player in &mut self.players {
+                player.record();
+            }
+        }
     }
 
-    /// Simulate moving a piece down
-    fn move_down(mut self) -> Self {
-        self.y_offset += 1;
-        self
+    /// Save the replay of the current game, if replays are being recorded.
+    ///
+    /// Versus games aren't saved, since the garbage each player gets depends
+    /// on how the other one plays, and neither are games that were never
+    /// played.
+    fn save_replay(&self) {
+        if self.players.len() > 1 || self.players[0].tetris.ticks == 0 {
+            return
+        }
+        if let Some(ref path) = self.record {
+            if let Err(err) = self.players[0].replay.save(path) {
+                eprintln!("Could not save replay to {}: {}", path.display(), err);
+            }
+        }
     }
+}
 
-    fn move_right(mut self) -> Self {
-        self.x_offset += 1;
-        self
+/// Everything the compositor keeps track of between callbacks.
+struct State {
+    /// Every game being played, one per output unless outputs show stats.
+    games: Vec<Game>,
+    /// The names of the outputs showing each game, so that an output that
+    /// comes back gets its game back.
+    screens: Vec<String>,
+    /// Which game the keyboard plays.
+    focus: usize,
+    layout: Layout,
+    /// The settings for any more games that are started.
+    mode: Mode,
+    /// Every mode that can be picked to play.
+    modes: Vec<Mode>,
+    handling: Handling,
+    seed: Option<u64>,
+    text: Text,
+    /// The timer on the event loop that steps the games.
+    tick_timer: *mut wl_event_source,
+    /// When the games were last caught up to.
+    tick_time: Instant,
+    /// How much time has passed that the games haven't been stepped for yet.
+    lag: Duration,
+    /// Whether to show where the falling piece will land.
+    ghost: bool,
+    bindings: Bindings
+}
+
+impl State {
+    fn new(options: Options, playback: Option<Playback>) -> Self {
+        let font_data = include_bytes!("../Roboto-Regular.ttf");
+        let font = Font::from_bytes(font_data as &[u8])
+            .expect("Error constructing Font");
+        let game = Game::new(options.mode,
+                             options.handling,
+                             options.seed,
+                             options.record,
+                             playback);
+        State { games: vec![game],
+                screens: Vec::new(),
+                focus: 0,
+                layout: options.layout,
+                mode: options.mode,
+                modes: vec![Mode::Marathon,
+                            Mode::Sprint,
+                            Mode::Ultra(options.ultra_seconds.unwrap_or(ULTRA_SECONDS)),
+                            Mode::Dig,
+                            Mode::Versus],
+                handling: options.handling,
+                seed: options.seed,
+                text: Text::new(font),
+                tick_timer: ptr::null_mut(),
+                tick_time: Instant::now(),
+                lag: Duration::from_secs(0),
+                ghost: true,
+                bindings: Bindings::load() }
     }
 
-    fn move_left(mut self) -> Self {
-        self.x_offset -= 1;
-        self
+    /// Decide what a new output shows, starting a new game for it if each
+    /// output gets its own.
+    fn add_screen(&mut self, name: String) -> Screen {
+        if let Some(index) = self.screens.iter().position(|screen| *screen == name) {
+            return Screen::Game(index)
+        }
+        if self.layout == Layout::Primary && !self.screens.is_empty() {
+            return Screen::Stats
+        }
+        if self.screens.len() == self.games.len() {
+            let game = Game::new(self.mode, self.handling, self.seed, None, None);
+            self.games.push(game);
+        }
+        self.screens.push(name);
+        Screen::Game(self.screens.len() - 1)
     }
 
-    fn rotate(mut self, dir: Dir) -> Self {
-        {
-            let center = self.center;
-            let mut data = [&mut self.data.0,
-                        &mut self.data.1,
-                        &mut self.data.2,
-                        &mut self.data.3];
-            match dir {
-                Dir::Right => {
-                    for ref mut d in data.iter_mut() {
-                        let temp = -(d.x - center.x) + center.y;
-                        d.x = d.y - center.y + center.x;
-                        d.y = temp;
-                    }
-                },
-                Dir::Left => {
-                    for ref mut d in data.iter_mut() {
-                        let temp = -(d.y - center.y) + center.x;
-                        d.y = d.x - center.x + center.y;
-                        d.x = temp;
-                    }
-                }
+    /// Step every game forward, by however many ticks have passed since
+    /// they were last caught up.
+    fn catch_up(&mut self) {
+        let now = Instant::now();
+        self.lag += now.duration_since(self.tick_time);
+        self.tick_time = now;
+        // Step in fixed ticks, so that replays play out the same way
+        let tick = Duration::new(0, TICK_NANOS);
+        while self.lag >= tick {
+            self.lag -= tick;
+            for game in &mut self.games {
+                game.tick();
             }
         }
-        self
     }
 
-    fn color(self) -> Color {
-        use Color::*;
-        use PieceType::*;
-        match self.ty {
-            Block => Blue,
-            L => Orange,
-            I => Pink,
-            J => Red,
-            T => Yellow,
-            S => Purple,
-            Z => Green
+    /// End every game and stop the compositor.
+    fn quit(&mut self) {
+        for game in &mut self.games {
+            game.end();
         }
+        wlroots::terminate()
     }
 
-    /// Get an iterator over the grid-level coordinates.
-    fn coords(self) -> [Origin; 4] {
-        let mut res = [self.data.0,
-                       self.data.1,
-                       self.data.2,
-                       self.data.3];
-        for origin in &mut res {
-            origin.x += self.x_offset;
-            origin.y += self.y_offset;
+    /// Open the pause menu, close it again, or go back to the title screen
+    /// once a game is over.
+    fn toggle_pause(&mut self) {
+        let game = &mut self.games[self.focus];
+        if game.playback.is_some() {
+            return
+        }
+        match game.menu.as_ref().map(|menu| menu.page) {
+            None if game.over() => game.menu = Some(Menu::title()),
+            None => game.pause(),
+            Some(Page::Pause) => game.resume(),
+            Some(_) => {}
         }
-        res
     }
-}
 
-impl Color {
-    fn border() -> Self {
-        Color::Grey
+    /// Move the selection of the focused game's menu up or down.
+    fn select(&mut self, up: bool) {
+        if let Some(ref mut menu) = self.games[self.focus].menu {
+            if up {
+                menu.select_previous()
+            } else {
+                menu.select_next()
+            }
+        }
     }
 
-    fn background() -> Self {
-        Color::DarkGrey
+    /// Use the focused game's menu with the keys for an action, to change
+    /// settings, pick entries and go back.
+    fn navigate(&mut self, action: Action) {
+        let (page, entry) = match self.games[self.focus].menu {
+            Some(ref menu) => (menu.page, menu.entry()),
+            None => return
+        };
+        match (action, page) {
+            // Flick through the high score tables for each mode
+            (Action::Left, Page::HighScores(mode)) | (Action::Right, Page::HighScores(mode)) => {
+                let ranked: Vec<Mode> =
+                    self.modes.iter().cloned().filter(|&mode| mode != Mode::Versus).collect();
+                let index = ranked.iter().position(|&other| other == mode).unwrap_or(0);
+                let index = match action {
+                    Action::Left => (index + ranked.len() - 1) % ranked.len(),
+                    _ => (index + 1) % ranked.len()
+                };
+                self.games[self.focus].menu = Some(high_scores(ranked[index]));
+            },
+            (Action::Left, _) => self.adjust(entry, false),
+            (Action::Right, _) => self.adjust(entry, true),
+            (Action::RotateRight, _) => self.pick(),
+            (Action::RotateLeft, _) => self.back(),
+            _ => {}
+        }
     }
 
-    fn dead() -> Self {
-        Color::TransparentRed
+    /// Change the setting an entry is for, up or down.
+    fn adjust(&mut self, entry: Entry, up: bool) {
+        let step = |value: u64, by: u64| if up { value + by } else { value.saturating_sub(by) };
+        match entry {
+            Entry::Das => self.handling.das = step(self.handling.das, DAS_STEP),
+            Entry::Arr => self.handling.arr = step(self.handling.arr, ARR_STEP),
+            Entry::Ghost => self.ghost = !self.ghost,
+            _ => {}
+        }
     }
 
-    fn paused() -> Self {
-        Color::TransparentBlue
+    /// Do whatever the picked out entry of the focused game's menu does.
+    fn pick(&mut self) {
+        let entry = match self.games[self.focus].menu {
+            Some(ref menu) => menu.entry(),
+            None => return
+        };
+        let handling = self.handling;
+        let menu = match entry {
+            Entry::Play => Menu::modes(&self.modes, self.mode),
+            Entry::Settings => Menu::settings(),
+            Entry::HighScores => {
+                high_scores(if self.mode == Mode::Versus { Mode::Marathon } else { self.mode })
+            },
+            Entry::Quit => return self.quit(),
+            Entry::Mode(mode) => {
+                self.mode = mode;
+                return self.games[self.focus].start(mode, handling)
+            },
+            Entry::Das | Entry::Arr | Entry::Ghost => return self.adjust(entry, true),
+            Entry::Back => return self.back(),
+            Entry::Resume => return self.games[self.focus].resume(),
+            Entry::Restart => return self.games[self.focus].restart(handling),
+            Entry::Title => {
+                self.games[self.focus].end();
+                Menu::title()
+            }
+        };
+        self.games[self.focus].menu = Some(menu);
     }
-}
 
-impl Into<[f32; 4]> for Color {
-    fn into(self) -> [f32; 4] {
-        use Color::*;
-        match self {
-            Blue => [0.0, 0.0, 1.0, 1.0],
-            Orange => [1.0, 0.41, 0.0, 1.0],
-            Yellow => [1.0, 1.0, 0.0, 1.0],
-            Red => [1.0, 0.0, 0.0, 1.0],
-            Green => [0.0, 1.0, 0.0, 1.0],
-            Pink => [1.0, 0.4117, 0.713, 1.0],
-            Purple => [0.9333, 0.50980, 0.9333, 1.0],
-            Grey => [0.50, 0.50, 0.50, 1.0],
-            DarkGrey => [0.25, 0.25, 0.25, 1.0],
-            TransparentRed => [0.5, 0.0, 0.0, 0.1],
-            TransparentBlue => [0.0, 0.0, 0.5, 0.1],
+    /// Go back from the focused game's menu.
+    fn back(&mut self) {
+        let game = &mut self.games[self.focus];
+        match game.menu.as_ref().map(|menu| menu.page) {
+            Some(Page::Title) | None => {},
+            Some(Page::Pause) => game.resume(),
+            Some(_) => game.menu = Some(Menu::title())
         }
     }
-}
 
-#[derive(Clone, Copy)]
-enum Dir {
-    Left,
-    Right
+    /// How many milliseconds until the next tick is due.
+    ///
+    /// The timer only counts whole milliseconds, so this rounds up and leaves
+    /// `catch_up` to make up the difference.
+    fn until_next_tick(&self) -> c_int {
+        let left = TICK_NANOS - self.lag.subsec_nanos();
+        ((left + 999999) / 1000000) as c_int
+    }
 }
 
-#[derive(Default, Clone, Copy)]
-struct Handler;
-
-#[derive(Clone)]
-struct Tetris {
-    board: [[Option<Color>; BOARD_WIDTH]; BOARD_HEIGHT],
-    current: Piece,
-    next: Piece,
-    time: Instant,
-    down: bool,
-    lost: bool,
-    pause: bool,
-    font: Font<'static>,
-    score: usize
+/// How games are spread across the outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// The first output has the only game, and the others show its stats.
+    Primary,
+    /// Every output has its own game.
+    Separate
 }
 
-impl Default for Tetris {
+impl Default for Layout {
     fn default() -> Self {
-        let font_data = include_bytes!("../Roboto-Regular.ttf");
-        let font = Font::from_bytes(font_data as &[u8])
-            .expect("Error constructing Font");
-        Tetris { board: [[None; BOARD_WIDTH]; BOARD_HEIGHT],
-                 current: Piece::random(),
-                 next: Piece::random().tease(),
-                 time: Instant::now(),
-                 down: false,
-                 lost: false,
-                 pause: false,
-                 font,
-                 score: 0 }
+        Layout::Primary
     }
 }
 
-impl Tetris {
-    /// Attempts to move the current piece in the given direction.
-    ///
-    /// If it would be blocked, then it will not change.
-    fn move_dir(&mut self, dir: Dir) {
-        let next_move = match dir {
-            Dir::Left => self.current.move_left(),
-            Dir::Right => self.current.move_right()
-        };
-        if self.collide(next_move.coords()) {
-            return
-        }
-        self.current = next_move
-    }
+/// What an output shows.
+#[derive(Debug, Clone, Copy)]
+enum Screen {
+    /// One of the games being played.
+    Game(usize),
+    /// The stats of the first game.
+    Stats
+}
 
-    /// Attempt to rotate the current piece in the given direction.
-    ///
-    /// If it would be blocked, then it will not change.
-    fn rotate(&mut self, dir: Dir) {
-        let next_move = self.current.rotate(dir);
-        if self.collide(next_move.coords()) {
-            return
-        }
-        self.current = next_move
+/// Everyone who plays a game like this one, starting from the same seed.
+fn players(tetris: Tetris) -> Vec<Player> {
+    match tetris.mode {
+        Mode::Versus => {
+            vec![Player::new(tetris.clone(), Seat::VersusLeft),
+                 Player::new(tetris, Seat::VersusRight)]
+        },
+        _ => vec![Player::new(tetris, Seat::Solo)]
     }
+}
 
-    /// Determines if the next step collides it the board with a piece
-    fn collide(&self, next: [Origin; 4]) -> bool {
-        for coord in next.into_iter() {
-            if coord.y >= BOARD_HEIGHT as i32 || coord.y < 0 {
-                return true
-            }
-            if coord.x >= BOARD_WIDTH as i32 || coord.x < 0 {
-                return true
-            }
-            if self.board[coord.y as usize][coord.x as usize].is_some() {
-                return true
-            }
-        }
-        false
-    }
+/// Settings that can be given on the command line.
+#[derive(Default)]
+struct Options {
+    mode: Mode,
+    layout: Layout,
+    /// How many seconds a score attack lasts.
+    ultra_seconds: Option<u64>,
+    handling: Handling,
+    seed: Option<u64>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>
+}
 
-    /// Clear any full rows that exist
-    fn clear_full_rows(&mut self) {
-        let mut rows = vec![];
-        'row_check: for (index, row) in self.board.iter_mut().enumerate() {
-            for block in row.iter_mut() {
-                if block.is_none() {
-                    continue 'row_check
+impl Options {
+    /// Parse `--mode <marathon|sprint|ultra|dig|versus>`,
+    /// `--outputs <primary|separate>`, `--ultra-seconds <seconds>`,
+    /// `--das <ms>`, `--arr <ms>`, `--seed <seed>`, `--record <file>` and
+    /// `--replay <file>` from the command line.
+    fn from_args() -> Self {
+        let mut options = Options::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => {
+                    eprintln!("Ignoring {}, which needs a value", arg);
+                    break
                 }
-            }
-            rows.push(index);
-            *row = [None; BOARD_WIDTH]
-        }
-        for row_index in rows {
-            self.score += 1;
-            let mut prev_index = row_index;
-            for above_index in (0..(row_index + 1)).rev() {
-                self.board[prev_index] = self.board[above_index];
-                prev_index = above_index;
+            };
+            let number = value.parse().ok();
+            match (arg.as_str(), number) {
+                ("--mode", _) => match Mode::from_name(&value) {
+                    Some(mode) => options.mode = mode,
+                    None => eprintln!("Ignoring unknown mode {}", value)
+                },
+                ("--outputs", _) => match value.as_str() {
+                    "primary" => options.layout = Layout::Primary,
+                    "separate" => options.layout = Layout::Separate,
+                    _ => eprintln!("Ignoring unknown output layout {}", value)
+                },
+                ("--ultra-seconds", Some(seconds)) => options.ultra_seconds = Some(seconds),
+                ("--das", Some(ms)) => options.handling.das = ms,
+                ("--arr", Some(ms)) => options.handling.arr = ms,
+                ("--seed", Some(seed)) => options.seed = Some(seed),
+                ("--record", _) => options.record = Some(value.into()),
+                ("--replay", _) => options.replay = Some(value.into()),
+                _ => eprintln!("Ignoring unknown argument {} {}", arg, value)
             }
         }
+        if let (Mode::Ultra(_), Some(seconds)) = (options.mode, options.ultra_seconds) {
+            options.mode = Mode::Ultra(seconds);
+        }
+        options
     }
 }
 
 fn main() {
-    CompositorBuilder::new().gles2(true)
-                            .output_manager(Box::new(Handler))
-                            .input_manager(Box::new(Handler))
-                            .build_auto(Tetris::default())
-                            .run()
+    let options = Options::from_args();
+    let playback = options.replay.as_ref().map(|path| match Replay::load(path) {
+        Ok(replay) => Playback::new(replay),
+        Err(err) => {
+            eprintln!("Could not load replay from {}: {}", path.display(), err);
+            std::process::exit(1)
+        }
+    });
+    let mut compositor = CompositorBuilder::new().gles2(true)
+                                                 .output_manager(Box::new(Handler))
+                                                 .input_manager(Box::new(Handler))
+                                                 .build_auto(State::new(options, playback));
+    // The games are stepped by a timer, so they keep the same pace no matter
+    // how many outputs there are or how often they draw
+    unsafe {
+        let timer = ffi_dispatch!(WAYLAND_SERVER_HANDLE,
+                                  wl_event_loop_add_timer,
+                                  compositor.event_loop,
+                                  Some(on_tick),
+                                  ptr::null_mut());
+        ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_timer_update, timer, 1);
+        let state: &mut State = (&mut compositor).into();
+        state.tick_timer = timer;
+    }
+    compositor.run()
 }
 
-impl OutputHandler for Handler {
+/// Step the games up to the current time, then wait for the next tick.
+unsafe extern "C" fn on_tick(_: *mut c_void) -> c_int {
+    let compositor = match wlroots::compositor_handle() {
+        Some(compositor) => compositor,
+        None => return 0
+    };
+    with_handles!([(compositor: {compositor})] => {
+        let state: &mut State = compositor.into();
+        state.catch_up();
+        let wait = state.until_next_tick();
+        ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_timer_update, state.tick_timer, wait);
+    }).ok();
+    0
+}
+
+impl OutputHandler for Screen {
     fn on_frame(&mut self, compositor: CompositorHandle, output: OutputHandle) {
         with_handles!([(compositor: {compositor}), (output: {output})] => {
-            let tetris: &mut Tetris = compositor.data.downcast_mut().unwrap();
-            let now = Instant::now();
-            let delta = now.duration_since(tetris.time);
-            let seconds_delta = delta.as_secs();
-            let nano_delta = delta.subsec_nanos() as u64;
-            let ms = (seconds_delta * 1000) + nano_delta / 1000000;
-            // Every half second simulate gravity
-            if (ms > 500 || tetris.down) && !tetris.lost && !tetris.pause {
-                tetris.down = false;
-                tetris.time = now;
-                let next_move = tetris.current.move_down();
-                // Check we don't collide.
-                // If we do, add it to the board and gen next falling piece
-                if tetris.collide(next_move.coords()) {
-                    let color = tetris.current.color();
-                    for coord in tetris.current.coords().into_iter() {
-                        tetris.board[coord.y as usize][coord.x as usize] = Some(color);
-                    }
-                    tetris.current = std::mem::replace(&mut tetris.next, Piece::random().tease()).reset();
-                    if tetris.collide(tetris.current.coords()) {
-                        tetris.lost = true;
-                    }
-                } else {
-                    tetris.current = next_move
-                }
-                tetris.clear_full_rows()
-            }
-            if ms > 1500 && tetris.lost {
-                *tetris = Tetris::default()
-            }
+            let state: &State = compositor.data.downcast_ref().unwrap();
             let (x_res, y_res) = output.effective_resolution();
-            let board_start_x = x_res / 4;
             let renderer = compositor.renderer.as_mut().expect("No renderer");
             let mut renderer = renderer.render(output, None);
-            let transform_matrix = renderer.output.transform_matrix();
             renderer.clear([0.0, 0.0, 0.0, 1.0]);
-            let block_width = x_res / (BOARD_WIDTH as i32 * 2);
-            let block_height = y_res / (BOARD_HEIGHT + 2) as i32;
-            let board_start_y = block_height;
-            // Render the border of the board
-            let block_size = Size::new(block_width as i32, block_height as i32);
-            for row in 0..(BOARD_WIDTH + 2) {
-                for column in 0..(BOARD_HEIGHT + 2) {
-                    let color = match (row, column) {
-                        (0, _) |
-                        (BOARD_WIDTH_EDGE, _) |
-                        (_, BOARD_HEIGHT_EDGE) => Color::border(),
-                        (_, _) => Color::background()
-                    };
-                    let mut area = Area::new(Origin::new(board_start_x +
-                                                     (block_width as i32 * row as i32)
-                                                     - block_width as i32,
-                                                     board_start_y +
-                                                     (block_height as i32 * column as i32)
-                                                     - block_height as i32),
-                                         block_size);
-                    let mut inner_box = area;
-                    inner_box.size.width -= block_width as i32 / 8;
-                    inner_box.origin.x -= block_width as i32 / 8;
-                    inner_box.size.height -= block_height as i32 / 8;
-                    inner_box.origin.y -= block_height as i32 / 8;
-                    renderer.render_scissor(inner_box);
-                    renderer.render_colored_rect(area, color.into(), transform_matrix);
-                    renderer.render_scissor(None);
-                }
-            }
-            // Render the rows in the board
-            let mut origin = Origin::new(board_start_x, board_start_y);
-            for row in &mut tetris.board {
-                origin.x = board_start_x;
-                for block in row.iter_mut() {
-                    let color = match *block {
-                        None => {
-                            origin.x += block_width as i32;
-                            continue
-                        },
-                        Some(color) => color
-                    };
-                    let area = Area::new(origin, block_size);
-                    let mut inner_box = area;
-                    inner_box.size.width -= block_width as i32 / 8;
-                    inner_box.origin.x -= block_width as i32 / 8;
-                    inner_box.size.height -= block_height as i32 / 8;
-                    inner_box.origin.y -= block_height as i32 / 8;
-                    renderer.render_scissor(inner_box);
-                    origin.x += block_width as i32;
-                    renderer.render_colored_rect(area, color.into(), transform_matrix);
-                    renderer.render_scissor(None);
+            match *self {
+                Screen::Game(index) => {
+                    let game = &state.games[index];
+                    // The game only shows behind the pause menu
+                    let playing = game.menu.as_ref().map_or(true, |menu| menu.page == Page::Pause);
+                    // Split the output between the players, side by side
+                    let width = x_res / game.players.len() as i32;
+                    for (index, player) in game.players.iter().enumerate().filter(|_| playing) {
+                        let viewport = Area::new(Origin::new(width * index as i32, 0),
+                                                 Size::new(width, y_res));
+                        render_game(&mut renderer, &state.text, player, state.ghost, viewport);
+                    }
+                    if let Some(ref menu) = game.menu {
+                        let viewport = Area::new(Origin::new(0, 0), Size::new(x_res, y_res));
+                        render_menu(&mut renderer, &state.text, menu, state, viewport);
+                    }
+                },
+                Screen::Stats => {
+                    let viewport = Area::new(Origin::new(0, 0), Size::new(x_res, y_res));
+                    render_stats(&mut renderer, &state.text, &state.games[0], viewport);
                 }
-                origin.y += block_height as i32;
             }
-            // Render the current falling piece on the board
-            let current_color = tetris.current.color();
-            for block in tetris.current.coords().into_iter() {
-                let x = board_start_x + (block_width as i32 * block.x);
-                let y = board_start_y + (block_height as i32 * block.y);
-                let area = Area::new(Origin::new(x, y), block_size);
-                let mut inner_box = area;
-                inner_box.size.width -= block_width as i32 / 8;
-                inner_box.origin.x -= block_width as i32 / 8;
-                inner_box.size.height -= block_height as i32 / 8;
-                inner_box.origin.y -= block_height as i32 / 8;
-                renderer.render_scissor(inner_box);
-                renderer.render_colored_rect(area, current_color.into(), transform_matrix);
-                renderer.render_scissor(None);
+        }).unwrap();
+    }
+}
+
+/// Render a menu, with its entries in a column and the picked out one
+/// highlighted.
+fn render_menu(renderer: &mut Renderer,
+               text: &Text,
+               menu: &Menu,
+               state: &State,
+               viewport: Area) {
+    let block_width = viewport.size.width / (BOARD_WIDTH as i32 * 2);
+    let block_height = viewport.size.height / (BOARD_HEIGHT + 2) as i32;
+    let text_size = Size::new(block_width, block_height);
+    let start_x = viewport.origin.x + viewport.size.width / 4;
+    let line_origin = |line: usize| {
+        Origin::new(start_x, viewport.origin.y + block_height * (line as i32 + 2))
+    };
+    text.render(renderer, menu.heading(), Area::new(line_origin(0), text_size), Align::Left);
+    for (line, words) in menu.lines.iter().enumerate() {
+        text.render(renderer, words, Area::new(line_origin(line + 2), text_size), Align::Left);
+    }
+    let first_entry = menu.lines.len() + 3;
+    let transform_matrix = renderer.output.transform_matrix();
+    for (index, entry) in menu.entries.iter().enumerate() {
+        let origin = line_origin(first_entry + index);
+        if index == menu.selected {
+            let area = Area::new(Origin::new(origin.x - block_width / 2, origin.y),
+                                 Size::new(viewport.size.width / 2, block_height));
+            renderer.render_scissor(area);
+            renderer.render_colored_rect(area, Color::border().into(), transform_matrix);
+            renderer.render_scissor(None);
+        }
+        let label = entry.label(state.handling, state.ghost);
+        text.render(renderer, &label, Area::new(origin, text_size), Align::Left);
+    }
+}
+
+/// The high score table for a mode, as a menu.
+fn high_scores(mode: Mode) -> Menu {
+    let table = scores::load(mode);
+    let mut lines = vec![format!("< {} >", mode_title(mode))];
+    if table.is_empty() {
+        lines.push("No games yet".to_string());
+    }
+    for (place, record) in table.iter().enumerate() {
+        lines.push(format!("{}. {} {}L {} {} #{}",
+                           place + 1,
+                           record.score,
+                           record.lines,
+                           format_time(record.time),
+                           format_date(record.date),
+                           record.seed));
+    }
+    Menu::high_scores(mode, lines)
+}
+
+/// Render the stats of everyone playing a game, in a column each.
+fn render_stats(renderer: &mut Renderer, text: &Text, game: &Game, viewport: Area) {
+    let width = viewport.size.width / game.players.len() as i32;
+    let line_height = viewport.size.height / (BOARD_HEIGHT + 2) as i32;
+    let text_size = Size::new(width / (BOARD_WIDTH as i32 * 2), line_height);
+    for (index, player) in game.players.iter().enumerate() {
+        let tetris = &player.tetris;
+        let stats = [tetris.mode.name().to_string(),
+                     format!("Score {}", tetris.score),
+                     format!("Lines {}", tetris.lines),
+                     format!("Level {}", tetris.level),
+                     format!("Time {}", format_time(tetris.elapsed)),
+                     format!("PPS {:.2}", tetris.pieces_per_second()),
+                     format!("Faults {}", tetris.finesse_faults)];
+        for (line, stat) in stats.iter().enumerate() {
+            let origin = Origin::new(viewport.origin.x + width * index as i32 + text_size.width,
+                                     viewport.origin.y + line_height * (line as i32 + 1));
+            text.render(renderer, stat, Area::new(origin, text_size), Align::Left);
+        }
+    }
+}
+
+/// Render a player's game, fitting it into the viewport.
+fn render_game(renderer: &mut Renderer,
+               text: &Text,
+               player: &Player,
+               ghost: bool,
+               viewport: Area) {
+    let tetris = &player.tetris;
+    let (x_res, y_res) = (viewport.size.width, viewport.size.height);
+    let board_start_x = viewport.origin.x + x_res / 4;
+    let block_width = x_res / (BOARD_WIDTH as i32 * 2);
+    let block_height = y_res / (BOARD_HEIGHT + 2) as i32;
+    let board_start_y = viewport.origin.y + block_height;
+    // Render the border of the board
+    let block_size = Size::new(block_width as i32, block_height as i32);
+    for row in 0..(BOARD_WIDTH + 2) {
+        for column in 0..(BOARD_HEIGHT + 2) {
+            let color = match (row, column) {
+                (0, _) |
+                (BOARD_WIDTH_EDGE, _) |
+                (_, BOARD_HEIGHT_EDGE) => Color::border(),
+                (_, _) => Color::background()
+            };
+            let area = Area::new(Origin::new(board_start_x +
+                                             (block_width as i32 * row as i32)
+                                             - block_width as i32,
+                                             board_start_y +
+                                             (block_height as i32 * column as i32)
+                                             - block_height as i32),
+                                 block_size);
+            render_block(renderer, area, color.into());
+        }
+    }
+    // Render the garbage waiting to rise up, just outside the left border
+    for row in 0..tetris.pending_garbage.min(BOARD_HEIGHT) {
+        let y = board_start_y + block_height * (BOARD_HEIGHT - 1 - row) as i32;
+        let area = Area::new(Origin::new(board_start_x - 2 * block_width, y), block_size);
+        render_block(renderer, area, Color::Red.into());
+    }
+    // Render the rows in the board
+    let mut origin = Origin::new(board_start_x, board_start_y);
+    for row in &tetris.board {
+        origin.x = board_start_x;
+        for block in row.iter() {
+            if let Some(color) = *block {
+                render_block(renderer, Area::new(origin, block_size), color.into());
             }
-            // Render the next piece off the side
-            let current_color = tetris.next.color();
-            for block in tetris.next.coords().into_iter() {
-                let x = board_start_x + (block_width as i32 * block.x);
-                let y = board_start_y + (block_height as i32 * block.y);
-                let area = Area::new(Origin::new(x, y), block_size);
-                let mut inner_box = area;
-                inner_box.size.width -= block_width as i32 / 8;
-                inner_box.origin.x -= block_width as i32 / 8;
-                inner_box.size.height -= block_height as i32 / 8;
-                inner_box.origin.y -= block_height as i32 / 8;
-                renderer.render_scissor(inner_box);
-                renderer.render_colored_rect(area, current_color.into(), transform_matrix);
-                renderer.render_scissor(None);
+            origin.x += block_width as i32;
+        }
+        origin.y += block_height as i32;
+    }
+    let piece_area = |block: &Point| {
+        let x = board_start_x + (block_width as i32 * block.x);
+        let y = board_start_y + (block_height as i32 * block.y);
+        Area::new(Origin::new(x, y), block_size)
+    };
+    // Render the current falling piece on the board, and where it will land,
+    // though nothing is falling once the game is over
+    if !tetris.over() {
+        if ghost {
+            let ghost_color = tetris.current.color().ghost();
+            for block in tetris.drop_position().coords().into_iter() {
+                render_block(renderer, piece_area(block), ghost_color);
             }
+        }
+        let current_color = tetris.current.color();
+        for block in tetris.current.coords().into_iter() {
+            render_block(renderer, piece_area(block), current_color.into());
+        }
+    }
+    // Render the next piece off the side
+    let next_color = tetris.next.color();
+    for block in tetris.next.coords().into_iter() {
+        render_block(renderer, piece_area(block), next_color.into());
+    }
+    // Render the held piece off the other side, greyed out if it
+    // can't be swapped back in yet
+    if let Some(hold) = tetris.hold {
+        let hold_color = if tetris.can_hold { hold.color() } else { Color::border() };
+        for block in hold.coords().into_iter() {
+            render_block(renderer, piece_area(block), hold_color.into());
+        }
+    }
 
-            // Render something indicating "you died"
-            if tetris.lost {
-                let area = Area::new(Origin::new(0, 0), Size::new(x_res, y_res));
-                renderer.render_scissor(area);
-                renderer.render_colored_rect(area, Color::dead().into(), transform_matrix);
-                renderer.render_scissor(None);
+    let transform_matrix = renderer.output.transform_matrix();
+    // Render something indicating "you died"
+    if tetris.lost {
+        renderer.render_scissor(viewport);
+        renderer.render_colored_rect(viewport, Color::dead().into(), transform_matrix);
+        renderer.render_scissor(None);
+    }
+    if tetris.pause {
+        renderer.render_scissor(viewport);
+        renderer.render_colored_rect(viewport, Color::paused().into(), transform_matrix);
+        renderer.render_scissor(None);
+    }
+    // Render the stats in labeled panels down the right of the board, under
+    // the next piece
+    let side_x = board_start_x + block_width * (BOARD_WIDTH as i32 + 1);
+    let side_area = |row: i32| {
+        Area::new(Origin::new(side_x, board_start_y + block_height * row),
+                  Size::new(block_width * 4, block_height))
+    };
+    text.render(renderer, "Next", side_area(-1), Align::Center);
+    let (lines_label, lines) = match tetris.mode {
+        Mode::Sprint => ("Lines", format!("{}/{}", tetris.lines.min(SPRINT_LINES), SPRINT_LINES)),
+        Mode::Dig => ("Dug", format!("{}/{}", DIG_ROWS - tetris.garbage_rows(), DIG_ROWS)),
+        _ => ("Lines", tetris.lines.to_string())
+    };
+    // Count down the time left in a score attack, otherwise count up
+    let time = format_time(tetris.time_left().unwrap_or(tetris.elapsed));
+    let panels = [("Score", tetris.score.to_string()),
+                  (lines_label, lines),
+                  ("Level", tetris.level.to_string()),
+                  ("Time", time),
+                  ("PPS", format!("{:.2}", tetris.pieces_per_second()))];
+    for (index, &(label, ref value)) in panels.iter().enumerate() {
+        let row = 4 + index as i32 * 3;
+        text.render(renderer, label, side_area(row), Align::Left);
+        text.render(renderer, value, side_area(row + 1), Align::Right);
+    }
+    // Render the results once the game is over, with the seed so the game
+    // can be played again
+    if tetris.over() {
+        let results = results(player);
+        for (line, result) in results.iter().enumerate() {
+            let area = Area::new(Origin::new(board_start_x,
+                                             board_start_y + block_height * (4 + line as i32)),
+                                 Size::new(block_width * BOARD_WIDTH as i32, block_height));
+            text.render(renderer, result, area, Align::Center);
+        }
+    }
+    // Call out any special clears next to the board
+    if let Some((ref callout, _)) = tetris.callout {
+        text.render(renderer, callout, side_area(19), Align::Left);
+    }
+}
+
+/// The lines of the results screen for a game that's over.
+fn results(player: &Player) -> Vec<String> {
+    let tetris = &player.tetris;
+    let mut results = vec![match (tetris.mode, tetris.finished) {
+                               (Mode::Versus, true) => "Winner".to_string(),
+                               (_, true) => "Finished".to_string(),
+                               (_, false) => "Game over".to_string()
+                           },
+                           format!("Score {}", tetris.score),
+                           format!("Lines {}", tetris.lines),
+                           format!("Level {}", tetris.level),
+                           format!("Time {}", format_time(tetris.elapsed)),
+                           format!("PPS {:.2}", tetris.pieces_per_second())];
+    match tetris.mode {
+        Mode::Versus => results.push(format!("Sent {}", tetris.garbage_sent)),
+        mode if mode.is_race() => results.push(format!("Faults {}", tetris.finesse_faults)),
+        _ => {}
+    }
+    match (player.place, player.previous_best) {
+        (Some(0), _) => results.push("New best!".to_string()),
+        (place, best) => {
+            if let Some(place) = place {
+                results.push(format!("High score #{}", place + 1));
             }
-            if tetris.pause {
-                let area = Area::new(Origin::new(0, 0), Size::new(x_res, y_res));
-                renderer.render_scissor(area);
-                renderer.render_colored_rect(area, Color::paused().into(), transform_matrix);
-                renderer.render_scissor(None);
+            if let Some(best) = best {
+                results.push(format!("Best {}", format_record(tetris.mode, &best)));
             }
-            // Render score
-            let scale = Scale::uniform(32.0);
-            let mut area = Area::new(Origin::new(0, 0), Size::new(block_width, block_height));
-            let score_str = tetris.score.to_string();
-            let v_metrics = tetris.font.v_metrics(scale);
-            // layout the glyphs in a line with 5 pixel padding
-            let glyphs: Vec<_> = tetris.font
-                .layout(score_str.as_str(), scale, rusttype::point(5.0, 5.0 + v_metrics.ascent))
-                .collect();
-            // work out the layout size
-            let glyphs_height = (v_metrics.ascent - v_metrics.descent).ceil() as u32;
-            let glyphs_width = {
-                let min_x = glyphs
-                    .first()
-                    .map(|g| g.pixel_bounding_box().unwrap().min.x)
-                    .unwrap();
-                let max_x = glyphs
-                    .last()
-                    .map(|g| g.pixel_bounding_box().unwrap().max.x)
-                    .unwrap();
-                (max_x - min_x) as u32
-            };
-            // Loop through the glyphs in the text, positing each one on a line
-            for glyph in glyphs {
-                // Draw the glyph into the image per-pixel by using the draw closure
-                let mut bytes = vec![0u8; (glyphs_width * 4 * glyphs_height) as usize];
-                glyph.draw(|x, y, v| {
-                    let index = ((x * 4) + (y * glyphs_width * 4) ) as usize;
-                    bytes[index + 0] = (v * 255.0) as u8;
-                    bytes[index + 1] = (v * 255.0) as u8;
-                    bytes[index+ 2] = (v * 255.0) as u8;
-                });
-                let texture = renderer.create_texture_from_pixels(wlroots::wl_shm_format::WL_SHM_FORMAT_ARGB8888,
-                                                                    glyphs_width * 4,
-                                                                    glyphs_width,
-                                                                    glyphs_height,
-                                                                    &bytes)
-                    .expect("Could not construct texture");
-                let transform = renderer.output.get_transform().invert();
-                let matrix = wlroots::project_box(area,
-                                                    transform,
-                                                    0.0,
-                                                    renderer.output.transform_matrix());
-                area.origin.x += area.size.width / 2 ;
-                renderer.render_texture_with_matrix(&texture, matrix);
-            }
-        }).unwrap();
+        }
+    }
+    results.push(format!("Seed {}", tetris.seed));
+    results.push("Enter to play again".to_string());
+    results.push("Pause for the menu".to_string());
+    results
+}
+
+/// Format what a record for a mode is ranked by, as a time for a race or a
+/// score for anything else.
+fn format_record(mode: Mode, record: &Record) -> String {
+    if mode.is_race() {
+        format_time(record.time)
+    } else {
+        record.score.to_string()
     }
 }
 
+/// Render a single block of the board, with a gap around it.
+fn render_block(renderer: &mut Renderer, area: Area, color: [f32; 4]) {
+    let transform_matrix = renderer.output.transform_matrix();
+    let mut inner_box = area;
+    inner_box.size.width -= area.size.width / 8;
+    inner_box.origin.x -= area.size.width / 8;
+    inner_box.size.height -= area.size.height / 8;
+    inner_box.origin.y -= area.size.height / 8;
+    renderer.render_scissor(inner_box);
+    renderer.render_colored_rect(area, color, transform_matrix);
+    renderer.render_scissor(None);
+}
+
+/// Format a duration like a stopwatch, as minutes, seconds and milliseconds.
+fn format_time(duration: Duration) -> String {
+    let millis = duration.subsec_millis();
+    let seconds = duration.as_secs();
+    format!("{}:{:02}.{:03}", seconds / 60, seconds % 60, millis)
+}
+
+/// Format seconds since the Unix epoch as a UTC date, like `2018-04-01`.
+fn format_date(seconds: u64) -> String {
+    // Count from 0000-03-01, so that leap days come at the end of the year
+    let days = seconds / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                       day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}-{:02}-{:02}", year, month, day)
+}
+
 impl KeyboardHandler for Handler {
     fn on_key(&mut self, compositor: CompositorHandle, _: KeyboardHandle, event: &KeyEvent) {
         with_handles!([(compositor: {compositor})] => {
-            let tetris: &mut Tetris = compositor.into();
-            if event.key_state() == WLR_KEY_PRESSED {
-                for key in event.pressed_keys() {
-                    match key {
-                        KEY_Escape => wlroots::terminate(),
-                        KEY_Down => {
-                            let mut prev_move = tetris.current;
-                            let mut next_move = tetris.current.move_down();
-                            while !tetris.collide(next_move.coords()) {
-                                prev_move = next_move;
-                                next_move = next_move.move_down();
+            let state: &mut State = compositor.into();
+            let pressed = event.key_state() == WLR_KEY_PRESSED;
+            let keysyms = event.pressed_keys();
+            let keycode = event.keycode();
+            let commands = state.bindings.commands(&keysyms, keycode);
+            let handling = state.handling;
+            if pressed {
+                for &command in &commands {
+                    let in_menu = state.games[state.focus].menu.is_some();
+                    match command {
+                        Command::Quit => state.quit(),
+                        Command::Restart => state.games[state.focus].restart(handling),
+                        Command::Confirm if in_menu => state.pick(),
+                        Command::Confirm => {
+                            if state.games[state.focus].over() {
+                                state.games[state.focus].restart(handling)
                             }
-                            tetris.current = prev_move;
-                            tetris.down = true;
                         },
-                        KEY_r => *tetris = Tetris::default(),
-                        KEY_space => tetris.pause = !tetris.pause,
-                        KEY_Left => tetris.move_dir(Dir::Left),
-                        KEY_Right => tetris.move_dir(Dir::Right),
-                        KEY_z => tetris.rotate(Dir::Left),
-                        KEY_x => tetris.rotate(Dir::Right),
-                        _ => {}
+                        Command::ToggleGhost => state.ghost = !state.ghost,
+                        Command::Focus => state.focus = (state.focus + 1) % state.games.len(),
+                        Command::Pause => state.toggle_pause(),
+                        Command::MenuUp if in_menu => state.select(true),
+                        Command::MenuDown if in_menu => state.select(false),
+                        Command::MenuUp | Command::MenuDown => {}
+                    }
+                }
+            }
+            let seats: Vec<Seat> =
+                state.games[state.focus].players.iter().map(|player| player.seat).collect();
+            let in_menu = state.games[state.focus].menu.is_some();
+            if pressed && in_menu {
+                for seat in seats {
+                    for action in state.bindings.actions(seat, &keysyms, keycode) {
+                        state.navigate(action);
+                    }
+                }
+            }
+            let game = &mut state.games[state.focus];
+            // The replay has all the inputs while it's playing, and keys that
+            // are let go still need to reach the game behind a menu
+            if game.playback.is_none() && !(pressed && in_menu) {
+                for player in &mut game.players {
+                    for action in state.bindings.actions(player.seat, &keysyms, keycode) {
+                        let input = if pressed {
+                            Input::Press(action)
+                        } else {
+                            Input::Release(action)
+                        };
+                        player.inputs.push(input);
                     }
                 }
             }
@@ -620,9 +985,33 @@ impl InputManagerHandler for Handler {
 
 impl OutputManagerHandler for Handler {
     fn output_added<'output>(&mut self,
-                             _: CompositorHandle,
+                             compositor: CompositorHandle,
                              builder: OutputBuilder<'output>)
                              -> Option<OutputBuilderResult<'output>> {
-        Some(builder.build_best_mode(Handler))
+        let name = builder.output().name();
+        let screen = with_handles!([(compositor: {compositor})] => {
+            let state: &mut State = compositor.into();
+            state.add_screen(name)
+        }).ok()?;
+        Some(builder.build_best_mode(screen))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_around_leap_days() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(946598400), "1999-12-31");
+        assert_eq!(format_date(951782400), "2000-02-29");
+        assert_eq!(format_date(951868800), "2000-03-01");
+        assert_eq!(format_date(1677542400), "2023-02-28");
+        assert_eq!(format_date(1677628800), "2023-03-01");
+        assert_eq!(format_date(1709164800 + 86399), "2024-02-29");
+        // 2100 isn't a leap year
+        assert_eq!(format_date(4107456000), "2100-02-28");
+        assert_eq!(format_date(4107542400), "2100-03-01");
     }
 }
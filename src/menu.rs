@@ -0,0 +1,135 @@
+//! The menus shown before, between and during games.
+
+use tetris_wlroots::{Handling, Mode};
+
+/// Which menu is showing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    Title,
+    Modes,
+    Settings,
+    /// The high score table for a mode.
+    HighScores(Mode),
+    Pause
+}
+
+/// Something that can be picked from a menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+    Play,
+    Settings,
+    HighScores,
+    Quit,
+    /// Start a game of the mode.
+    Mode(Mode),
+    Das,
+    Arr,
+    Ghost,
+    Back,
+    Resume,
+    Restart,
+    /// End the game and go back to the title screen.
+    Title
+}
+
+impl Entry {
+    /// The text shown for the entry, with the current value of a setting.
+    pub fn label(self, handling: Handling, ghost: bool) -> String {
+        match self {
+            Entry::Play => "Play".into(),
+            Entry::Settings => "Settings".into(),
+            Entry::HighScores => "High scores".into(),
+            Entry::Quit => "Quit".into(),
+            Entry::Mode(mode) => mode_title(mode),
+            Entry::Das => format!("DAS {}ms", handling.das),
+            Entry::Arr => format!("ARR {}ms", handling.arr),
+            Entry::Ghost => format!("Ghost {}", if ghost { "on" } else { "off" }),
+            Entry::Back => "Back".into(),
+            Entry::Resume => "Resume".into(),
+            Entry::Restart => "Restart".into(),
+            Entry::Title => "Quit to title".into()
+        }
+    }
+}
+
+/// A menu, and which of its entries is picked out.
+pub struct Menu {
+    pub page: Page,
+    pub entries: Vec<Entry>,
+    /// Text shown above the entries.
+    pub lines: Vec<String>,
+    pub selected: usize
+}
+
+impl Menu {
+    fn new(page: Page, entries: Vec<Entry>) -> Self {
+        Menu { page, entries, lines: Vec::new(), selected: 0 }
+    }
+
+    pub fn title() -> Self {
+        Menu::new(Page::Title,
+                  vec![Entry::Play, Entry::Settings, Entry::HighScores, Entry::Quit])
+    }
+
+    /// Pick which mode to play, starting on the one played last.
+    pub fn modes(modes: &[Mode], last: Mode) -> Self {
+        let mut entries: Vec<_> = modes.iter().map(|&mode| Entry::Mode(mode)).collect();
+        entries.push(Entry::Back);
+        let mut menu = Menu::new(Page::Modes, entries);
+        menu.selected = modes.iter().position(|&mode| mode == last).unwrap_or(0);
+        menu
+    }
+
+    pub fn settings() -> Self {
+        Menu::new(Page::Settings,
+                  vec![Entry::Das, Entry::Arr, Entry::Ghost, Entry::Back])
+    }
+
+    pub fn high_scores(mode: Mode, lines: Vec<String>) -> Self {
+        let mut menu = Menu::new(Page::HighScores(mode), vec![Entry::Back]);
+        menu.lines = lines;
+        menu
+    }
+
+    pub fn pause() -> Self {
+        Menu::new(Page::Pause,
+                  vec![Entry::Resume, Entry::Restart, Entry::Title])
+    }
+
+    /// The heading shown at the top of the menu.
+    pub fn heading(&self) -> &'static str {
+        match self.page {
+            Page::Title => "Tetris",
+            Page::Modes => "Play",
+            Page::Settings => "Settings",
+            Page::HighScores(_) => "High scores",
+            Page::Pause => "Paused"
+        }
+    }
+
+    /// The entry that's picked out.
+    pub fn entry(&self) -> Entry {
+        self.entries[self.selected]
+    }
+
+    /// Move down to the next entry, wrapping around to the top.
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.entries.len();
+    }
+
+    /// Move up to the previous entry, wrapping around to the bottom.
+    pub fn select_previous(&mut self) {
+        self.selected = (self.selected + self.entries.len() - 1) % self.entries.len();
+    }
+}
+
+/// The name of a mode, as it's shown in menus.
+pub fn mode_title(mode: Mode) -> String {
+    match mode {
+        Mode::Marathon => "Marathon".into(),
+        Mode::Sprint => "Sprint".into(),
+        Mode::Ultra(seconds) => format!("Ultra {}s", seconds),
+        Mode::Dig => "Dig".into(),
+        Mode::Versus => "Versus".into()
+    }
+}
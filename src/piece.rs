@@ -0,0 +1,425 @@
+use bag::Bag;
+use color::Color;
+use rand::Rng;
+use BOARD_WIDTH;
+
+/// A cell on the board, where `y` grows downward.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32
+}
+
+impl Point {
+    pub fn new(x: i32, y: i32) -> Self {
+        Point { x, y }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PieceData(pub Point, pub Point, pub Point, pub Point);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceType {
+    Block,
+    L,
+    I,
+    J,
+    T,
+    S,
+    Z
+}
+
+pub const PIECE_TYPES: [PieceType; 7] = [PieceType::Block,
+                                         PieceType::L,
+                                         PieceType::I,
+                                         PieceType::J,
+                                         PieceType::T,
+                                         PieceType::S,
+                                         PieceType::Z];
+
+/// The four orientations a piece can be in, as tracked by the Super Rotation
+/// System.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Spawn,
+    Right,
+    Flip,
+    Left
+}
+
+impl Rotation {
+    pub fn rotate(self, dir: Dir) -> Self {
+        use Rotation::*;
+        match (self, dir) {
+            (Spawn, Dir::Right) | (Flip, Dir::Left) => Right,
+            (Right, Dir::Right) | (Left, Dir::Left) => Flip,
+            (Flip, Dir::Right) | (Spawn, Dir::Left) => Left,
+            (Left, Dir::Right) | (Right, Dir::Left) => Spawn
+        }
+    }
+
+    /// Index into the wall kick tables for rotating from this state.
+    fn kick_index(self, dir: Dir) -> usize {
+        let from = match self {
+            Rotation::Spawn => 0,
+            Rotation::Right => 1,
+            Rotation::Flip => 2,
+            Rotation::Left => 3
+        };
+        match dir {
+            Dir::Right => from * 2,
+            Dir::Left => from * 2 + 1
+        }
+    }
+}
+
+/// SRS wall kick offsets for the J, L, S, T and Z pieces, tried in order.
+///
+/// The rows are 0->R, 0->L, R->2, R->0, 2->L, 2->R, L->0, L->2.
+/// The y values are flipped from the usual tables because rows grow downward.
+const JLSTZ_KICKS: [[(i32, i32); 5]; 8] = [[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+                                           [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+                                           [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+                                           [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+                                           [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+                                           [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+                                           [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+                                           [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]];
+
+/// SRS wall kick offsets for the I piece, laid out like `JLSTZ_KICKS`.
+const I_KICKS: [[(i32, i32); 5]; 8] = [[(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+                                       [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+                                       [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+                                       [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+                                       [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+                                       [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+                                       [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+                                       [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)]];
+
+impl PieceType {
+    /// The blocks of the piece in its spawn orientation, inside its
+    /// bounding box.
+    pub fn origin(self) -> PieceData {
+        use PieceType::*;
+        match self {
+            S => {
+                let a = Point::new(1, 0);
+                let b = Point::new(2, 0);
+                let c = Point::new(0, 1);
+                let d = Point::new(1, 1);
+                PieceData(a, b, c, d)
+
+            }
+            Block => {
+                let a = Point::new(0, 0);
+                let b = Point::new(1, 0);
+                let c = Point::new(0, 1);
+                let d = Point::new(1, 1);
+                PieceData(a, b, c, d)
+
+            },
+            L => {
+                let a = Point::new(2, 0);
+                let b = Point::new(0, 1);
+                let c = Point::new(1, 1);
+                let d = Point::new(2, 1);
+                PieceData(a, b, c, d)
+            },
+            I => {
+                let a = Point::new(0, 1);
+                let b = Point::new(1, 1);
+                let c = Point::new(2, 1);
+                let d = Point::new(3, 1);
+                PieceData(a, b, c, d)
+            },
+            J => {
+                let a = Point::new(0, 0);
+                let b = Point::new(0, 1);
+                let c = Point::new(1, 1);
+                let d = Point::new(2, 1);
+                PieceData(a, b, c, d)
+            },
+            T => {
+                let a = Point::new(1, 0);
+                let b = Point::new(0, 1);
+                let c = Point::new(1, 1);
+                let d = Point::new(2, 1);
+                PieceData(a, b, c, d)
+            },
+            Z => {
+                let a = Point::new(0, 0);
+                let b = Point::new(1, 0);
+                let c = Point::new(1, 1);
+                let d = Point::new(2, 1);
+                PieceData(a, b, c, d)
+            }
+        }
+    }
+
+    /// The width and height of the box the piece rotates within.
+    pub fn size(self) -> i32 {
+        match self {
+            PieceType::Block => 2,
+            PieceType::I => 4,
+            _ => 3
+        }
+    }
+
+    /// The wall kicks to try, in order, when rotating out of `rotation`.
+    pub fn kicks(self, rotation: Rotation, dir: Dir) -> &'static [(i32, i32)] {
+        match self {
+            PieceType::Block => &[(0, 0)],
+            PieceType::I => &I_KICKS[rotation.kick_index(dir)],
+            _ => &JLSTZ_KICKS[rotation.kick_index(dir)]
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Piece {
+    pub data: PieceData,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub rotation: Rotation,
+    pub ty: PieceType
+}
+
+impl Piece {
+    /// Spawn a piece of the given type at the top center of the board.
+    pub fn new(ty: PieceType) -> Self {
+        let data = ty.origin();
+        let x_offset = (BOARD_WIDTH as i32 - ty.size()) / 2;
+        let mut piece = Piece { ty, x_offset, y_offset: 0, rotation: Rotation::Spawn, data };
+        piece.y_offset = piece.spawn_y();
+        piece
+    }
+
+    /// Deal the next piece out of the bag.
+    pub fn random<R: Rng>(bag: &mut Bag, rng: &mut R) -> Self {
+        Piece::new(bag.next(rng))
+    }
+
+    /// The offset that puts the top of a freshly spawned piece on the top row.
+    pub fn spawn_y(self) -> i32 {
+        let mut top = self.data.0.y;
+        for point in [self.data.1, self.data.2, self.data.3].iter() {
+            top = top.min(point.y);
+        }
+        -top
+    }
+
+    /// Put the piece floating to the top right
+    pub fn tease(self) -> Self {
+        let mut piece = self.reset();
+        piece.x_offset = BOARD_WIDTH as i32 + 1;
+        piece
+    }
+
+    /// Put the piece floating to the top left, in the hold slot.
+    pub fn stash(self) -> Self {
+        let mut piece = self.reset();
+        piece.x_offset = -1 - self.ty.size();
+        piece
+    }
+
+    /// Reset to the center of the screen, in the spawn orientation.
+    pub fn reset(self) -> Self {
+        Piece::new(self.ty)
+    }
+
+    /// Simulate moving a piece down
+    pub fn move_down(mut self) -> Self {
+        self.y_offset += 1;
+        self
+    }
+
+    pub fn move_right(mut self) -> Self {
+        self.x_offset += 1;
+        self
+    }
+
+    pub fn move_left(mut self) -> Self {
+        self.x_offset -= 1;
+        self
+    }
+
+    /// Rotate the piece within its bounding box, without any wall kicks.
+    pub fn rotate(mut self, dir: Dir) -> Self {
+        {
+            let last = self.ty.size() - 1;
+            let mut data = [&mut self.data.0,
+                            &mut self.data.1,
+                            &mut self.data.2,
+                            &mut self.data.3];
+            for d in data.iter_mut() {
+                let (x, y) = (d.x, d.y);
+                match dir {
+                    Dir::Right => {
+                        d.x = last - y;
+                        d.y = x;
+                    },
+                    Dir::Left => {
+                        d.x = y;
+                        d.y = last - x;
+                    }
+                }
+            }
+        }
+        self.rotation = self.rotation.rotate(dir);
+        self
+    }
+
+    /// Offset the piece by a wall kick.
+    pub fn kick(mut self, (x, y): (i32, i32)) -> Self {
+        self.x_offset += x;
+        self.y_offset += y;
+        self
+    }
+
+    /// Rotate the piece with SRS, trying each wall kick in order until one
+    /// leaves it somewhere it fits.
+    ///
+    /// Returns the rotated piece and which kick was used, or `None` if every
+    /// kick is blocked.
+    pub fn rotate_kicked<F: Fn(Piece) -> bool>(self, dir: Dir, fits: F) -> Option<(Self, usize)> {
+        let rotated = self.rotate(dir);
+        self.ty
+            .kicks(self.rotation, dir)
+            .iter()
+            .map(|&kick| rotated.kick(kick))
+            .enumerate()
+            .find(|&(_, piece)| fits(piece))
+            .map(|(index, piece)| (piece, index))
+    }
+
+    pub fn color(self) -> Color {
+        use Color::*;
+        use PieceType::*;
+        match self.ty {
+            Block => Blue,
+            L => Orange,
+            I => Pink,
+            J => Red,
+            T => Yellow,
+            S => Purple,
+            Z => Green
+        }
+    }
+
+    /// Get an iterator over the grid-level coordinates.
+    pub fn coords(self) -> [Point; 4] {
+        let mut res = [self.data.0,
+                       self.data.1,
+                       self.data.2,
+                       self.data.3];
+        for point in &mut res {
+            point.x += self.x_offset;
+            point.y += self.y_offset;
+        }
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dir {
+    Left,
+    Right
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::{Action, Handling, Input, Mode, Tetris};
+    use std::time::Duration;
+
+    /// A game on an empty board, with a freshly spawned piece of the type.
+    fn game_with(ty: PieceType) -> Tetris {
+        let mut tetris = Tetris::new(Mode::Marathon, Handling::default(), 0);
+        tetris.current = Piece::new(ty);
+        tetris
+    }
+
+    fn press(tetris: &mut Tetris, action: Action) {
+        tetris.step(Duration::from_secs(0), &[Input::Press(action)]);
+    }
+
+    fn columns(piece: Piece) -> Vec<i32> {
+        let mut columns: Vec<_> = piece.coords().iter().map(|point| point.x).collect();
+        columns.sort();
+        columns.dedup();
+        columns
+    }
+
+    #[test]
+    fn spawned_i_rotates_without_kicking() {
+        let spawn = Piece::new(PieceType::I);
+        let mut tetris = game_with(PieceType::I);
+        press(&mut tetris, Action::RotateRight);
+        assert_eq!(columns(tetris.current), vec![5]);
+        assert_eq!(tetris.current.y_offset, spawn.y_offset);
+        let mut tetris = game_with(PieceType::I);
+        press(&mut tetris, Action::RotateLeft);
+        assert_eq!(columns(tetris.current), vec![4]);
+        assert_eq!(tetris.current.y_offset, spawn.y_offset);
+    }
+
+    #[test]
+    fn jlstz_kick_off_the_wall() {
+        let mut tetris = game_with(PieceType::T);
+        let mut piece = Piece::new(PieceType::T).rotate(Dir::Right);
+        piece.x_offset = -1;
+        piece.y_offset = 5;
+        tetris.current = piece;
+        // Pointing up doesn't fit against the wall, so the second kick
+        // moves it out by one
+        press(&mut tetris, Action::RotateLeft);
+        assert_eq!(tetris.current.rotation, Rotation::Spawn);
+        assert_eq!((tetris.current.x_offset, tetris.current.y_offset), (0, 5));
+    }
+
+    #[test]
+    fn i_kicks_off_the_wall() {
+        let mut tetris = game_with(PieceType::I);
+        let mut piece = Piece::new(PieceType::I).rotate(Dir::Right);
+        piece.x_offset = -2;
+        piece.y_offset = 5;
+        tetris.current = piece;
+        // Only the third I kick moves it far enough from the wall, which the
+        // other pieces don't have
+        press(&mut tetris, Action::RotateRight);
+        assert_eq!(tetris.current.rotation, Rotation::Flip);
+        assert_eq!((tetris.current.x_offset, tetris.current.y_offset), (0, 5));
+    }
+
+    #[test]
+    fn blocked_rotations_do_nothing() {
+        let mut tetris = game_with(PieceType::T);
+        tetris.current.y_offset = 10;
+        let spawn = tetris.current;
+        // Box the T in, so that every kick is blocked
+        for row in tetris.board.iter_mut() {
+            for (column, block) in row.iter_mut().enumerate() {
+                if !(3..=5).contains(&column) {
+                    *block = Some(Color::Red);
+                }
+            }
+        }
+        tetris.board[9][3] = Some(Color::Red);
+        tetris.board[12] = [Some(Color::Red); BOARD_WIDTH];
+        press(&mut tetris, Action::RotateRight);
+        assert_eq!(tetris.current.rotation, Rotation::Spawn);
+        assert_eq!(tetris.current.coords(), spawn.coords());
+    }
+
+    #[test]
+    fn rotating_four_times_comes_back_around() {
+        for &ty in PIECE_TYPES.iter() {
+            let piece = Piece::new(ty);
+            let right = piece.rotate(Dir::Right).rotate(Dir::Right).rotate(Dir::Right);
+            assert_eq!(right.rotate(Dir::Right).coords(), piece.coords());
+            assert_eq!(right.rotation, Rotation::Left);
+            assert_eq!(piece.rotate(Dir::Left).coords(), right.coords());
+        }
+    }
+}
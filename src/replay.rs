@@ -0,0 +1,216 @@
+use game::{Action, Handling, Input, Mode, Tetris};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// Everything needed to play a game again exactly as it happened: its mode,
+/// its seed, its handling settings and every input along with the tick it
+/// happened on.
+///
+/// Replays are stored as plain text, one entry per line:
+///
+/// ```text
+/// mode sprint
+/// seed 1234
+/// das 167
+/// arr 33
+/// 60 press left
+/// 64 release left
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    pub mode: Mode,
+    pub seed: u64,
+    pub handling: Handling,
+    pub inputs: Vec<(u64, Input)>
+}
+
+impl Replay {
+    pub fn new(mode: Mode, seed: u64, handling: Handling) -> Self {
+        Replay { mode, seed, handling, inputs: Vec::new() }
+    }
+
+    /// Start recording a game.
+    pub fn of(tetris: &Tetris) -> Self {
+        Replay::new(tetris.mode, tetris.seed, tetris.handling)
+    }
+
+    /// Start a new game set up the same way as the recorded one.
+    pub fn game(&self) -> Tetris {
+        Tetris::new(self.mode, self.handling, self.seed)
+    }
+
+    /// Remember an input that was given to the game on the given tick.
+    pub fn record(&mut self, tick: u64, input: Input) {
+        self.inputs.push((tick, input))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        match self.mode {
+            Mode::Ultra(seconds) => writeln!(file, "mode ultra {}", seconds)?,
+            mode => writeln!(file, "mode {}", mode.name())?
+        }
+        writeln!(file, "seed {}", self.seed)?;
+        writeln!(file, "das {}", self.handling.das)?;
+        writeln!(file, "arr {}", self.handling.arr)?;
+        for &(tick, input) in &self.inputs {
+            let (state, action) = match input {
+                Input::Press(action) => ("press", action),
+                Input::Release(action) => ("release", action)
+            };
+            writeln!(file, "{} {} {}", tick, state, action.name())?;
+        }
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        let mut replay = Replay::new(Mode::default(), 0, Handling::default());
+        for (number, line) in file.lines().enumerate() {
+            let line = line?;
+            let words: Vec<_> = line.split_whitespace().collect();
+            let invalid = || {
+                io::Error::new(io::ErrorKind::InvalidData,
+                               format!("Bad replay entry on line {}: {}", number + 1, line))
+            };
+            match words.as_slice() {
+                [] => {},
+                ["mode", mode] => replay.mode = Mode::from_name(mode).ok_or_else(invalid)?,
+                ["mode", "ultra", seconds] => {
+                    replay.mode = Mode::Ultra(seconds.parse().map_err(|_| invalid())?)
+                },
+                ["seed", seed] => replay.seed = seed.parse().map_err(|_| invalid())?,
+                ["das", das] => replay.handling.das = das.parse().map_err(|_| invalid())?,
+                ["arr", arr] => replay.handling.arr = arr.parse().map_err(|_| invalid())?,
+                [tick, state, action] => {
+                    let tick = tick.parse().map_err(|_| invalid())?;
+                    let action = Action::from_name(action).ok_or_else(invalid)?;
+                    let input = match *state {
+                        "press" => Input::Press(action),
+                        "release" => Input::Release(action),
+                        _ => return Err(invalid())
+                    };
+                    replay.record(tick, input)
+                },
+                _ => return Err(invalid())
+            }
+        }
+        Ok(replay)
+    }
+}
+
+/// Feeds the inputs of a replay back in, tick by tick.
+#[derive(Debug, Clone)]
+pub struct Playback {
+    pub replay: Replay,
+    /// The index of the next input to give out.
+    cursor: usize
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback { replay, cursor: 0 }
+    }
+
+    /// Start again from the beginning of the replay.
+    pub fn rewind(&mut self) {
+        self.cursor = 0
+    }
+
+    /// The inputs that happened on the given tick.
+    pub fn inputs(&mut self, tick: u64) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        while let Some(&(input_tick, input)) = self.replay.inputs.get(self.cursor) {
+            if input_tick > tick {
+                break
+            }
+            inputs.push(input);
+            self.cursor += 1;
+        }
+        inputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+    use temp_dir::TempDir;
+
+    /// What a player does on each tick, going through a bit of everything.
+    fn script(tick: u64) -> Vec<Input> {
+        use self::Action::*;
+        // Shift to either wall, or just once, so the pieces spread out
+        let turn = tick / 45;
+        let dir = if turn % 2 == 0 { Left } else { Right };
+        let release = if turn % 4 < 2 { 18 } else { 2 };
+        match tick % 45 {
+            0 if turn % 3 == 0 => vec![Input::Press(Hold)],
+            1 => vec![Input::Release(Hold), Input::Press(dir)],
+            time if time == release => vec![Input::Release(dir)],
+            20 => vec![Input::Press(SoftDrop), Input::Press(RotateRight)],
+            21 => vec![Input::Release(RotateRight)],
+            30 if turn % 5 == 0 => vec![Input::Release(SoftDrop), Input::Press(RotateLeft)],
+            30 => vec![Input::Release(SoftDrop)],
+            40 => vec![Input::Release(RotateLeft), Input::Press(HardDrop)],
+            41 => vec![Input::Release(HardDrop)],
+            _ => vec![]
+        }
+    }
+
+    #[test]
+    fn replays_load_back_what_was_saved() {
+        let handling = Handling { das: 120, arr: 0 };
+        let dir = TempDir::new("replay-round-trip");
+        for &mode in [Mode::Marathon, Mode::Sprint, Mode::Ultra(90)].iter() {
+            let mut replay = Replay::new(mode, 0xDEAD_BEEF_1234, handling);
+            for tick in 0..200 {
+                for input in script(tick) {
+                    replay.record(tick, input);
+                }
+            }
+            let path = dir.path().join(format!("{}.replay", mode.name()));
+            replay.save(&path).unwrap();
+            assert_eq!(Replay::load(&path).unwrap(), replay);
+        }
+    }
+
+    #[test]
+    fn bad_entries_are_rejected() {
+        let dir = TempDir::new("replay-bad");
+        let path = dir.path().join("bad.replay");
+        fs::write(&path, "mode sprint\nseed 1\n12 press nothing\n").unwrap();
+        let err = Replay::load(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn playing_back_gives_the_same_game() {
+        let tick = Duration::new(0, 1000000000 / 60);
+        let mut game = Tetris::new(Mode::Marathon, Handling::default(), 2018);
+        let mut replay = Replay::of(&game);
+        while game.ticks < 3000 && !game.over() {
+            let inputs = script(game.ticks);
+            for &input in &inputs {
+                replay.record(game.ticks, input);
+            }
+            game.step(tick, &inputs);
+        }
+        assert!(game.pieces > 20);
+        let dir = TempDir::new("replay-playback");
+        let path = dir.path().join("playback.replay");
+        replay.save(&path).unwrap();
+        let mut playback = Playback::new(Replay::load(&path).unwrap());
+        let mut again = playback.replay.game();
+        while again.ticks < game.ticks {
+            let inputs = playback.inputs(again.ticks);
+            again.step(tick, &inputs);
+        }
+        assert!(again.board == game.board);
+        assert_eq!(again.current.coords(), game.current.coords());
+        assert_eq!((again.score, again.lines, again.pieces), (game.score, game.lines, game.pieces));
+        assert_eq!(again.lost, game.lost);
+    }
+}
@@ -0,0 +1,227 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tetris_wlroots::{Mode, Tetris};
+
+/// How many records are kept for each mode.
+pub const TABLE_SIZE: usize = 10;
+
+/// A game on the high score table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub score: usize,
+    pub lines: usize,
+    /// How much time was played.
+    pub time: Duration,
+    /// When the game ended, in seconds since the Unix epoch.
+    pub date: u64,
+    pub seed: u64
+}
+
+impl Record {
+    /// The record for a game that's ended, if it can go on the table.
+    ///
+    /// Games against the clock only count if they were finished, and versus
+    /// games don't count at all, since they depend on the other player.
+    pub fn of(tetris: &Tetris) -> Option<Self> {
+        let counts = match tetris.mode {
+            Mode::Versus => false,
+            Mode::Marathon => tetris.pieces > 0,
+            _ => tetris.finished
+        };
+        if !counts {
+            return None
+        }
+        let date = SystemTime::now().duration_since(UNIX_EPOCH)
+                                    .map(|since| since.as_secs())
+                                    .unwrap_or(0);
+        Some(Record { score: tetris.score,
+                      lines: tetris.lines,
+                      time: tetris.elapsed,
+                      date,
+                      seed: tetris.seed })
+    }
+
+    /// Whether this ranks above another record for a mode, by taking less
+    /// time in a race or by scoring more in anything else.
+    pub fn beats(&self, other: &Record, mode: Mode) -> bool {
+        if mode.is_race() {
+            self.time < other.time
+        } else {
+            self.score > other.score
+        }
+    }
+
+    /// Parse a record from a line of `score lines milliseconds date seed`.
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Option<Vec<u64>> =
+            line.split_whitespace().map(|field| field.parse().ok()).collect();
+        match fields?[..] {
+            [score, lines, millis, date, seed] => {
+                Some(Record { score: score as usize,
+                              lines: lines as usize,
+                              time: Duration::from_millis(millis),
+                              date,
+                              seed })
+            },
+            _ => None
+        }
+    }
+}
+
+/// Where results are saved between games.
+///
+/// This is `$XDG_DATA_HOME/tetris-wlroots`, falling back to
+/// `~/.local/share/tetris-wlroots`.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share")
+    };
+    Some(base.join("tetris-wlroots"))
+}
+
+fn table_path(mode: Mode) -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join(format!("scores-{}", mode.table_name())))
+}
+
+/// The high score table for a mode, best first.
+pub fn load(mode: Mode) -> Vec<Record> {
+    table_path(mode).map(|path| read_table(&path)).unwrap_or_default()
+}
+
+/// Add a record to the table for a mode, returning where it placed if it
+/// made it on.
+pub fn add(mode: Mode, record: Record) -> io::Result<Option<usize>> {
+    let path = table_path(mode).ok_or_else(|| {
+                                                io::Error::new(io::ErrorKind::NotFound,
+                                                               "No home directory to save into")
+                                            })?;
+    add_to(&path, mode, record)
+}
+
+/// Read a table, skipping any lines that aren't records.
+fn read_table(path: &Path) -> Vec<Record> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Vec::new()
+    };
+    BufReader::new(file).lines()
+                        .filter_map(|line| line.ok().and_then(|line| Record::parse(&line)))
+                        .take(TABLE_SIZE)
+                        .collect()
+}
+
+fn add_to(path: &Path, mode: Mode, record: Record) -> io::Result<Option<usize>> {
+    let mut table = read_table(path);
+    let place = table.iter()
+                     .position(|other| record.beats(other, mode))
+                     .unwrap_or(table.len());
+    if place >= TABLE_SIZE {
+        return Ok(None)
+    }
+    table.insert(place, record);
+    table.truncate(TABLE_SIZE);
+    save(path, &table)?;
+    Ok(Some(place))
+}
+
+/// Write out a table.
+///
+/// It's written to a temporary file first, then moved over the old table,
+/// so that the table is never left half written.
+fn save(path: &Path, table: &[Record]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp_path = path.with_extension("tmp");
+    {
+        let mut file = File::create(&temp_path)?;
+        for record in table {
+            let millis = record.time.as_secs() * 1000 + record.time.subsec_millis() as u64;
+            writeln!(file,
+                     "{} {} {} {} {}",
+                     record.score,
+                     record.lines,
+                     millis,
+                     record.date,
+                     record.seed)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_dir::TempDir;
+
+    fn record(score: usize, millis: u64) -> Record {
+        Record { score,
+                 lines: score / 100,
+                 time: Duration::from_millis(millis),
+                 date: 1522540800,
+                 seed: score as u64 * 7 }
+    }
+
+    #[test]
+    fn tables_load_back_what_was_saved() {
+        let dir = TempDir::new("scores-round-trip");
+        let path = dir.path().join("scores-marathon");
+        assert_eq!(read_table(&path), vec![]);
+        let records = [record(300, 65432), record(100, 1), record(200, 120000)];
+        for &record in records.iter() {
+            add_to(&path, Mode::Marathon, record).unwrap();
+        }
+        assert_eq!(read_table(&path), vec![records[0], records[2], records[1]]);
+    }
+
+    #[test]
+    fn tables_keep_the_best_ten() {
+        let dir = TempDir::new("scores-truncate");
+        let path = dir.path().join("scores-marathon");
+        for score in 1..13 {
+            add_to(&path, Mode::Marathon, record(score * 100, 0)).unwrap();
+        }
+        let scores: Vec<_> = read_table(&path).iter().map(|record| record.score).collect();
+        assert_eq!(scores, (3..13).rev().map(|score| score * 100).collect::<Vec<_>>());
+        assert_eq!(add_to(&path, Mode::Marathon, record(200, 0)).unwrap(), None);
+        assert_eq!(add_to(&path, Mode::Marathon, record(550, 0)).unwrap(), Some(7));
+        assert_eq!(read_table(&path).len(), TABLE_SIZE);
+    }
+
+    #[test]
+    fn races_rank_by_time_and_the_rest_by_score() {
+        let fast = record(100, 30000);
+        let slow = record(900, 60000);
+        assert!(fast.beats(&slow, Mode::Sprint));
+        assert!(fast.beats(&slow, Mode::Dig));
+        assert!(slow.beats(&fast, Mode::Marathon));
+        assert!(slow.beats(&fast, Mode::Ultra(120)));
+        // Ties go to whoever got there first
+        assert!(!fast.beats(&fast, Mode::Sprint));
+        let dir = TempDir::new("scores-race");
+        let path = dir.path().join("scores-sprint");
+        assert_eq!(add_to(&path, Mode::Sprint, slow).unwrap(), Some(0));
+        assert_eq!(add_to(&path, Mode::Sprint, fast).unwrap(), Some(0));
+        assert_eq!(add_to(&path, Mode::Sprint, slow).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let dir = TempDir::new("scores-bad-lines");
+        let path = dir.path().join("scores-marathon");
+        fs::write(&path, "500 5 61000 1522540800 42\n\
+                          not a record\n\
+                          400 4 60000\n\
+                          -1 0 0 0 0\n\
+                          300 3 59000 1522540800 43 extra\n\
+                          200 2 58000 1522540800 44\n").unwrap();
+        let scores: Vec<_> = read_table(&path).iter().map(|record| record.score).collect();
+        assert_eq!(scores, vec![500, 200]);
+        assert_eq!(read_table(&path)[0].time, Duration::from_millis(61000));
+    }
+}
@@ -0,0 +1,172 @@
+//! Text, drawn from an atlas texture of every glyph that's been rendered.
+
+use rusttype::{self, Font, GlyphId, PositionedGlyph, Scale};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wlroots::{self, Area, Origin, Renderer, Size, Texture};
+
+/// How wide the atlas is, in pixels. It grows downwards as glyphs are added.
+const ATLAS_WIDTH: u32 = 1024;
+/// How much of the height of a line the glyphs take up.
+const LINE_FILL: f32 = 0.8;
+
+/// Which side of its area a line of text lines up with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right
+}
+
+/// Where a glyph is in the atlas.
+#[derive(Clone, Copy)]
+struct Cell {
+    area: Area,
+    /// Where the glyph's pixels start, from its position on the baseline.
+    offset: Origin
+}
+
+/// Every glyph rasterized so far, packed in rows.
+struct Atlas {
+    pixels: Vec<u8>,
+    height: u32,
+    /// The cells of glyphs by their id and line height, or `None` for glyphs
+    /// with nothing to draw, like spaces.
+    cells: HashMap<(GlyphId, u32), Option<Cell>>,
+    /// Where the next glyph goes.
+    cursor: Origin,
+    /// How tall the row the cursor is in is so far.
+    row_height: i32,
+    /// The pixels uploaded, or `None` if glyphs were added since.
+    texture: Option<Texture<'static>>
+}
+
+impl Atlas {
+    /// The cell of a glyph, rasterizing it the first time it's seen.
+    fn cell(&mut self, glyph: &PositionedGlyph<'static>, line_height: u32) -> Option<Cell> {
+        let key = (glyph.id(), line_height);
+        if let Some(&cell) = self.cells.get(&key) {
+            return cell
+        }
+        let glyph = glyph.unpositioned().clone().positioned(rusttype::point(0.0, 0.0));
+        let cell = glyph.pixel_bounding_box().map(|bounds| {
+            let size = Size::new(bounds.width(), bounds.height());
+            let origin = self.allocate(size);
+            let pixels = &mut self.pixels;
+            glyph.draw(|x, y, coverage| {
+                let index = ((origin.y as u32 + y) * ATLAS_WIDTH + origin.x as u32 + x) * 4;
+                // White, with the coverage as premultiplied alpha
+                let value = (coverage * 255.0).round() as u8;
+                for byte in &mut pixels[index as usize..index as usize + 4] {
+                    *byte = value;
+                }
+            });
+            Cell { area: Area::new(origin, size),
+                   offset: Origin::new(bounds.min.x, bounds.min.y) }
+        });
+        self.cells.insert(key, cell);
+        cell
+    }
+
+    /// Find room for a glyph, starting a new row once this one is full and
+    /// growing the atlas to fit.
+    fn allocate(&mut self, size: Size) -> Origin {
+        if self.cursor.x + size.width > ATLAS_WIDTH as i32 {
+            self.cursor = Origin::new(0, self.cursor.y + self.row_height);
+            self.row_height = 0;
+        }
+        let origin = self.cursor;
+        // Leave a gap so that no glyph bleeds into its neighbours
+        self.cursor.x += size.width + 1;
+        self.row_height = self.row_height.max(size.height + 1);
+        let height = (origin.y + size.height) as u32;
+        if height > self.height {
+            self.height = height;
+            self.pixels.resize((ATLAS_WIDTH * height * 4) as usize, 0);
+        }
+        self.texture = None;
+        origin
+    }
+
+    /// The atlas texture, uploading it again if glyphs were added since.
+    fn texture(&mut self, renderer: &mut Renderer) -> Option<&Texture<'static>> {
+        if self.texture.is_none() && self.height > 0 {
+            let format = wlroots::wl_shm_format::WL_SHM_FORMAT_ARGB8888;
+            let texture = renderer.create_texture_from_pixels(format,
+                                                              ATLAS_WIDTH * 4,
+                                                              ATLAS_WIDTH,
+                                                              self.height,
+                                                              &self.pixels)
+                                  .expect("Could not construct texture");
+            self.texture = Some(texture);
+        }
+        self.texture.as_ref()
+    }
+}
+
+/// A font, and the glyphs of it that have been rendered.
+pub struct Text {
+    font: Font<'static>,
+    atlas: RefCell<Atlas>
+}
+
+impl Text {
+    pub fn new(font: Font<'static>) -> Self {
+        Text { font,
+               atlas: RefCell::new(Atlas { pixels: Vec::new(),
+                                           height: 0,
+                                           cells: HashMap::new(),
+                                           cursor: Origin::new(0, 0),
+                                           row_height: 0,
+                                           texture: None }) }
+    }
+
+    /// Render a line of text sized to the height of an area, centered
+    /// vertically in it and lined up with one of its sides.
+    pub fn render(&self, renderer: &mut Renderer, text: &str, area: Area, align: Align) {
+        let line_height = area.size.height.max(1) as u32;
+        let scale = Scale::uniform(line_height as f32 * LINE_FILL);
+        let v_metrics = self.font.v_metrics(scale);
+        // The layout spaces the glyphs by their advances and kerning
+        let glyphs: Vec<_> = self.font.layout(text, scale, rusttype::point(0.0, 0.0)).collect();
+        let width = glyphs.last().map_or(0.0, |glyph| {
+            glyph.position().x + glyph.unpositioned().h_metrics().advance_width
+        }).ceil() as i32;
+        let start_x = match align {
+            Align::Left => area.origin.x,
+            Align::Center => area.origin.x + (area.size.width - width) / 2,
+            Align::Right => area.origin.x + area.size.width - width
+        };
+        let glyphs_height = v_metrics.ascent - v_metrics.descent;
+        let baseline = area.origin.y +
+                       ((line_height as f32 - glyphs_height) / 2.0 + v_metrics.ascent) as i32;
+        let mut atlas = self.atlas.borrow_mut();
+        let cells: Vec<_> = glyphs.iter()
+                                  .map(|glyph| (glyph.position().x.round() as i32,
+                                                atlas.cell(glyph, line_height)))
+                                  .collect();
+        let atlas_size = Size::new(ATLAS_WIDTH as i32, atlas.height as i32);
+        let texture = match atlas.texture(renderer) {
+            Some(texture) => texture,
+            None => return
+        };
+        let transform = renderer.output.get_transform().invert();
+        let transform_matrix = renderer.output.transform_matrix();
+        for (x, cell) in cells {
+            let cell = match cell {
+                Some(cell) => cell,
+                None => continue
+            };
+            let origin = Origin::new(start_x + x + cell.offset.x, baseline + cell.offset.y);
+            // Draw the whole atlas so the glyph's cell lands on its place in
+            // the line, cutting off everything around it
+            let atlas_area = Area::new(Origin::new(origin.x - cell.area.origin.x,
+                                                   origin.y - cell.area.origin.y),
+                                       atlas_size);
+            let matrix = wlroots::project_box(atlas_area, transform, 0.0, transform_matrix);
+            renderer.render_scissor(Area::new(origin, cell.area.size));
+            renderer.render_texture_with_matrix(texture, matrix);
+        }
+        renderer.render_scissor(None);
+    }
+}